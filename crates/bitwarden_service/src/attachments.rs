@@ -0,0 +1,82 @@
+use std::{fs, path::{Path, PathBuf}};
+
+use anyhow::{Context, Result, bail};
+
+use security::models::{self as sec_models, Decrypt};
+
+use crate::{constants, models, service};
+
+
+/// Loads attachments. They are kept encrypted in a directory next to the cached vault, so each one
+/// is downloaded only once.
+pub struct Fetcher<'a> {
+    config: &'a models::Config,
+    token: &'a models::TokenResponse,
+}
+
+impl<'a> Fetcher<'a> {
+    pub fn new(config: &'a models::Config, token: &'a models::TokenResponse) -> Self {
+        Self { config, token }
+    }
+
+    fn cache_path(&self, attachment_id: &str) -> Result<PathBuf> {
+        // the id ends up in a file name
+        if attachment_id.is_empty() || !attachment_id.chars().all(|c| c.is_ascii_alphanumeric() || c == '-') {
+            bail!("Invalid attachment id: {}", attachment_id);
+        }
+
+        Ok(Path::new(&self.config.config_dir)
+            .join(constants::ATTACHMENTS_DIRNAME)
+            .join(format!("{}.bin", attachment_id)))
+    }
+
+    /// The encrypted contents of an attachment, from the cache or downloaded
+    fn encrypted(&self, cipher_id: &uuid::Uuid, attachment_id: &str) -> Result<Vec<u8>> {
+        let path = self.cache_path(attachment_id)?;
+        let path_str = path.to_str().unwrap_or("");
+
+        if path.is_file() {
+            return fs::read(&path).context(format!("Could not read file {}", path_str));
+        }
+
+        let urls = self.config.server.urls();
+        let url = service::get_attachment_url(&urls, self.token, cipher_id, attachment_id)?;
+        let data = service::download_attachment(&url)?;
+
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)
+                .context(format!("Could not create directory {}", dir.to_str().unwrap_or("")))?;
+        }
+
+        // written under another name first, so an interrupted write doesn't leave a broken file
+        let partial = path.with_extension("part");
+        fs::write(&partial, &data)
+            .context(format!("Could not write to file {}", path_str))?;
+        fs::rename(&partial, &path)
+            .context(format!("Could not write to file {}", path_str))?;
+
+        Ok(data)
+    }
+
+    /// Downloads and decrypts an attachment of `cipher`, whose key is `sym_key`
+    pub fn fetch(
+        &self, cipher: &models::Cipher, attachment: &models::CipherAttachment, file_name: String,
+        sym_key: &sec_models::SymmetricKey,
+    ) -> Result<models::NoteAttachment> {
+
+        let key = match &attachment.key {
+            Some(v) => v
+                .decrypt(sym_key)
+                .and_then(crate::symmetric_key)
+                .context("Could not decrypt attachment key")?,
+            None => sym_key.clone()
+        };
+
+        let data = self.encrypted(&cipher.id, &attachment.id)?;
+        let data = sec_models::CipherString::from_buffer(&data)?
+            .decrypt(&key)
+            .context("Could not decrypt attachment")?;
+
+        Ok(models::NoteAttachment { id: attachment.id.clone(), file_name, data })
+    }
+}
@@ -1,34 +1,191 @@
-use std::path::Path;
+use std::{collections::HashMap, convert::TryFrom, path::Path};
 
-use anyhow::{Context, bail, Result};
-use security::models as sec_models;
+use anyhow::{anyhow, Context, bail, Result};
+use security::models::{self as sec_models, Decrypt};
 
-use crate::{constants, models::{self, Config}, service, store, utils::{read_from_stdin, process_conn_errors}};
+use crate::{constants, models::{self, Config, ServerSettings, TwoFactor, TwoFactorProvider}, service, store, utils::{read_from_stdin, process_conn_errors}};
 
 
+/// The API key to log in with, if one was configured
+fn api_key(config: &Config) -> Result<Option<(&str, &str)>> {
+    match (&config.bw_client_id, &config.bw_client_secret) {
+        (Some(id), Some(secret)) => Ok(Some((id, secret))),
+        (None, None) => Ok(None),
+        _ => bail!("NXCMDR_BW_CLIENT_ID and NXCMDR_BW_CLIENT_SECRET must be set together.")
+    }
+}
+
 fn get_new_token(config: &Config) -> anyhow::Result<models::TokenResponse> {
     let email = read_from_stdin(&config.bw_user, "Bitwarden email: ", false)?;
     if email == "" {
         bail!("Email was not provided.");
     }
 
-    let iterations = service::get_iterations(&email)?;
+    let urls = config.server.urls();
+    let kdf = service::get_kdf(&urls, &email)?;
 
     let password = read_from_stdin(&config.bw_pass, "Bitwarden password: ", true)?;
     if password == "" {
         bail!("Password was not provided.");
     }
 
-    let master_key = sec_models::MasterKey::from(
+    let master_key = sec_models::MasterKey::try_from(
         &sec_models::Credentials {
-            email: email.to_string(), password: password.to_string(), iterations});
+            email: email.to_string(), password: password.to_string(), kdf})?;
+
+    if let Some((client_id, client_secret)) = api_key(config)? {
+        let mut token = service::get_api_key_token(&urls, &config.device, client_id, client_secret)?;
+
+        // the server does not check the master password when logging in with an API key,
+        // so make sure it can decrypt the account key before it is cached
+        let key = match &token.key {
+            Some(v) => v,
+            None => bail!("Could not find the account key in token response")
+        };
+        let key: Result<sec_models::CipherString> = (&sec_models::StringWrapper::from(key.as_str())).into();
+        key?.decrypt(&master_key)
+            .context("Could not decrypt the account key. Is the master password correct?")?;
+
+        token.master_key = Some(base64::encode(&master_key.key));
+
+        return Ok(token);
+    }
+
+    login_with_password(config, &email, &master_key)
+}
+
+/// Picks the two-step login provider among the ones enabled for the account
+fn choose_provider(config: &Config, providers: &HashMap<String, Option<serde_json::Value>>)
+        -> Result<TwoFactorProvider> {
+
+    let enabled: Vec<TwoFactorProvider> = TwoFactorProvider::PROMPTABLE
+        .iter()
+        .copied()
+        .filter(|p| providers.contains_key(&p.id().to_string()))
+        .collect();
+    let names = enabled.iter().map(|p| p.name()).collect::<Vec<&str>>().join(", ");
+
+    if let Some(name) = &config.bw_tfa_provider {
+        let name = name.trim().to_lowercase();
+        return enabled
+            .iter()
+            .copied()
+            .find(|p| p.name() == name)
+            .ok_or_else(|| anyhow!("Two-step login provider {} is not enabled for this account (enabled: {})",
+                name, names));
+    }
+
+    // a code given through NXCMDR_BW_TFA has always been an authenticator code
+    if config.bw_tfa.is_some() && enabled.contains(&TwoFactorProvider::Authenticator) {
+        return Ok(TwoFactorProvider::Authenticator);
+    }
+
+    match enabled.len() {
+        0 => bail!("None of the two-step login providers enabled for this account are supported."),
+        1 => Ok(enabled[0]),
+        _ => {
+            println!("Two-step login providers:");
+            for (i, provider) in enabled.iter().enumerate() {
+                println!("  {}. {}", i + 1, provider.description());
+            }
+
+            let choice = read_from_stdin(&None, "Choose a provider: ", false)?;
+            choice
+                .parse::<usize>()
+                .ok()
+                .and_then(|i| i.checked_sub(1))
+                .and_then(|i| enabled.get(i))
+                .copied()
+                .ok_or_else(|| anyhow!("Invalid choice: {}", choice))
+        }
+    }
+}
+
+/// Logs in with the master password, asking for a second factor if the account has two-step login
+/// enabled. The token returned for remembering the device is stored encrypted with a key derived
+/// from the master key and used for the next login instead of asking again.
+fn login_with_password(config: &Config, email: &str, master_key: &sec_models::MasterKey)
+        -> Result<models::TokenResponse> {
+
+    let urls = config.server.urls();
+    let path = Path::new(&config.config_dir)
+        .join(constants::REMEMBER_FILENAME);
+    let key: Result<sec_models::SymmetricKey> = master_key.into();
+    let key = key?;
+
+    let remembered = store::load_stored_with::<String>(&path, &key)
+        .ok()
+        .map(|token| TwoFactor { provider: TwoFactorProvider::Remember, token, remember: false });
+
+    let mut res = service::get_new_token(&urls, &config.device, email, master_key, remembered.as_ref())?;
+
+    let providers = match res.two_factor_providers.take() {
+        Some(v) => v,
+        None => return Ok(res)
+    };
+
+    if remembered.is_some() {
+        // the server does not accept the remembered token anymore
+        std::fs::remove_file(&path).ok();
+    }
+
+    let provider = choose_provider(config, &providers)?;
 
-    let tfa_code = read_from_stdin(&config.bw_tfa, "TFA code: ", false)?;
-    if tfa_code == "" {
+    if provider == TwoFactorProvider::Email {
+        service::send_email_login(&urls, &config.device, email, master_key)?;
+
+        let address = providers
+            .get(&provider.id().to_string())
+            .and_then(|v| v.as_ref())
+            .and_then(|v| v.get("Email"))
+            .and_then(|v| v.as_str())
+            .unwrap_or("your email address");
+        println!("A login code was sent to {}.", address);
+    }
+
+    let code = read_from_stdin(&config.bw_tfa, &format!("{} code: ", provider.description()), false)?;
+    if code == "" {
         bail!("TFA code was not provided.");
     }
 
-    Ok(service::get_new_token(&email, &master_key, &tfa_code)?)
+    let two_factor = TwoFactor { provider, token: code, remember: true };
+    let mut res = service::get_new_token(&urls, &config.device, email, master_key, Some(&two_factor))?;
+
+    if res.two_factor_providers.is_some() {
+        bail!("Two-step login failed: {}", res.error_model
+            .and_then(|e| e.message)
+            .unwrap_or_else(|| "the code was not accepted".to_string()));
+    }
+
+    if let Some(token) = res.two_factor_token.take() {
+        store::store_data_with(&path, &token, &key)
+            .context("Could not save two-step login token")?;
+    }
+
+    Ok(res)
+}
+
+/// Gets a new access token with the refresh token, or by logging in with the API key again for
+/// tokens that were obtained with one
+fn renew_token(config: &Config, token: &mut models::TokenResponse) -> Result<()> {
+    let urls = config.server.urls();
+
+    if token.refresh_token.is_some() {
+        return service::refresh_token(&urls, token);
+    }
+
+    let (client_id, client_secret) = match api_key(config)? {
+        Some(v) => v,
+        None => bail!("Token expired and can't be refreshed. \
+            Set NXCMDR_BW_CLIENT_ID and NXCMDR_BW_CLIENT_SECRET to log in with the API key again.")
+    };
+
+    let mut renewed = service::get_api_key_token(&urls, &config.device, client_id, client_secret)?;
+    renewed.master_key = token.master_key.take();
+    renewed.server = token.server.take();
+    *token = renewed;
+
+    Ok(())
 }
 
 fn need_refresh(token: &models::TokenResponse) -> Result<bool> {
@@ -47,24 +204,48 @@ fn need_refresh(token: &models::TokenResponse) -> Result<bool> {
     Ok(last_saved + duration <= chrono::offset::Local::now())
 }
 
-pub fn get_token(ignore_conn_errors: bool, quiet: bool) -> anyhow::Result<models::TokenResponse> {
-    let config = Config::load(false)?;
+/// Loads the saved token, refreshing it if it expired. Logs in if there is no token for the server,
+/// which may prompt for the credentials.
+pub fn get_token(config: &Config, ignore_conn_errors: bool, quiet: bool) -> anyhow::Result<models::TokenResponse> {
+    load_token(config, true, ignore_conn_errors, quiet)
+}
+
+/// Like `get_token`, but never prompts: if there is no token for the server, this fails instead of
+/// logging in. For when a running command owns the terminal.
+pub fn get_saved_token(config: &Config, ignore_conn_errors: bool) -> anyhow::Result<models::TokenResponse> {
+    load_token(config, false, ignore_conn_errors, true)
+}
+
+fn load_token(config: &Config, login: bool, ignore_conn_errors: bool, quiet: bool)
+        -> anyhow::Result<models::TokenResponse> {
+
     let path = Path::new(&config.config_dir)
         .join(constants::TOKEN_FILENAME);
 
-    let data = store::load_stored(&path).ok();
+    let data = store::load_stored::<models::TokenResponse>(&path).ok();
+    let identity = config.server.urls().identity;
+    let default_identity = ServerSettings::default().urls().identity;
 
     let mut do_write = false;
 
     let mut data = match data {
-        Some(v) => v,
-        None => {
+        Some(v) if v.server.as_deref().unwrap_or(&default_identity) == identity => v,
+        data => {
+            if !login {
+                bail!("There is no saved token for this server. Run nxc on its own to log in again.");
+            }
             if !quiet {
-                println!("Could not read file: {}", path.to_str().unwrap_or("<unknown>"));
+                match data {
+                    Some(_) => println!("The saved token is for another server."),
+                    None => println!("Could not read file: {}", path.to_str().unwrap_or("<unknown>"))
+                }
             }
             Config::load(true)?;
             do_write = true;
-            get_new_token(&config)?
+
+            let mut token = get_new_token(config)?;
+            token.server = Some(identity);
+            token
         }
     };
 
@@ -75,7 +256,7 @@ pub fn get_token(ignore_conn_errors: bool, quiet: bool) -> anyhow::Result<models
         }
 
         process_conn_errors(
-            service::refresh_token(&mut data), (), ignore_conn_errors, quiet)?;
+            renew_token(config, &mut data), (), ignore_conn_errors, quiet)?;
 
         do_write = true;
     }
@@ -1,17 +1,11 @@
 pub const TOKEN_FILENAME: &str = "data1.bin";
 pub const DATA_FILENAME: &str = "data2.bin";
-pub const BW_API_BASE: &str = "https://vault.bitwarden.com/api";
-pub const BW_IDENTITY_BASE: &str = "https://vault.bitwarden.com/identity";
-
-
-#[macro_export]
-macro_rules! PRELOGIN_URL {() => { format!("{base}/accounts/prelogin", base = BW_API_BASE) }}
-
-#[macro_export]
-macro_rules! TOKEN_URL {() => { format!("{base}/connect/token", base = BW_IDENTITY_BASE) }}
-
-#[macro_export]
-macro_rules! SYNC_URL {() => { format!("{base}/sync", base = BW_API_BASE) }}
-
-#[macro_export]
-macro_rules! REVISION_URL {() => { format!("{base}/accounts/revision-date", base = BW_API_BASE) }}
+/// bumped whenever fields are added to the cached vault, so older caches are synced again
+/// instead of being read with the new fields missing
+pub const DATA_VERSION: u32 = 1;
+pub const REMEMBER_FILENAME: &str = "data3.bin";
+/// directory with the encrypted attachments that were downloaded
+pub const ATTACHMENTS_DIRNAME: &str = "attachments";
+pub const CONFIG_FILENAME: &str = "config.json";
+pub const DEVICE_FILENAME: &str = "device_id";
+pub const DEFAULT_SERVER: &str = "https://vault.bitwarden.com";
@@ -0,0 +1,218 @@
+use std::{cell::Cell, thread, time::{Duration, SystemTime, UNIX_EPOCH}};
+
+use anyhow::{Context, Result};
+use security::{models::{self as sec_models, Decrypt}, totp::Totp};
+
+use crate::models::{self, NoteField};
+
+
+/// Turns an item name into a variable prefix: `GitHub deploy key` becomes `GITHUB_DEPLOY_KEY`
+pub fn default_prefix(name: &str) -> String {
+    let mut out = String::new();
+
+    for c in name.chars() {
+        if c.is_ascii_alphanumeric() {
+            out.push(c.to_ascii_uppercase());
+        } else if !out.is_empty() && !out.ends_with('_') {
+            out.push('_');
+        }
+    }
+
+    out.trim_end_matches('_').to_string()
+}
+
+fn prefixed(prefix: &str, suffix: &str) -> String {
+    match prefix {
+        "" => suffix.to_string(),
+        prefix => format!("{}_{}", prefix, suffix)
+    }
+}
+
+/// A property of a login, card or identity
+struct Property<'a> {
+    /// the id linked custom fields refer to it by
+    linked_id: u32,
+    /// the name of the variable it is mapped to, after the prefix
+    suffix: &'static str,
+    value: &'a Option<sec_models::CipherString>,
+    sensitive: bool,
+}
+
+impl<'a> Property<'a> {
+    fn new(linked_id: u32, suffix: &'static str, value: &'a Option<sec_models::CipherString>, sensitive: bool)
+            -> Self {
+        Self { linked_id, suffix, value, sensitive }
+    }
+}
+
+/// The properties of the login, card or identity of an item, in the order they are mapped
+fn properties(cipher: &models::Cipher) -> Vec<Property<'_>> {
+    let mut out = Vec::new();
+
+    if let Some(login) = &cipher.login {
+        out.push(Property::new(100, "USERNAME", &login.username, false));
+        out.push(Property::new(101, "PASSWORD", &login.password, true));
+    }
+
+    if let Some(card) = &cipher.card {
+        out.push(Property::new(300, "CARDHOLDER_NAME", &card.cardholder_name, false));
+        out.push(Property::new(304, "BRAND", &card.brand, false));
+        out.push(Property::new(305, "NUMBER", &card.number, true));
+        out.push(Property::new(301, "EXP_MONTH", &card.exp_month, false));
+        out.push(Property::new(302, "EXP_YEAR", &card.exp_year, false));
+        out.push(Property::new(303, "CODE", &card.code, true));
+    }
+
+    if let Some(identity) = &cipher.identity {
+        out.push(Property::new(400, "TITLE", &identity.title, false));
+        out.push(Property::new(416, "FIRST_NAME", &identity.first_name, false));
+        out.push(Property::new(401, "MIDDLE_NAME", &identity.middle_name, false));
+        out.push(Property::new(417, "LAST_NAME", &identity.last_name, false));
+        out.push(Property::new(402, "ADDRESS1", &identity.address1, false));
+        out.push(Property::new(403, "ADDRESS2", &identity.address2, false));
+        out.push(Property::new(404, "ADDRESS3", &identity.address3, false));
+        out.push(Property::new(405, "CITY", &identity.city, false));
+        out.push(Property::new(406, "STATE", &identity.state, false));
+        out.push(Property::new(407, "POSTAL_CODE", &identity.postal_code, false));
+        out.push(Property::new(408, "COUNTRY", &identity.country, false));
+        out.push(Property::new(409, "COMPANY", &identity.company, false));
+        out.push(Property::new(410, "EMAIL", &identity.email, false));
+        out.push(Property::new(411, "PHONE", &identity.phone, false));
+        out.push(Property::new(412, "SSN", &identity.ssn, true));
+        out.push(Property::new(413, "USERNAME", &identity.username, false));
+        out.push(Property::new(414, "PASSPORT_NUMBER", &identity.passport_number, true));
+        out.push(Property::new(415, "LICENSE_NUMBER", &identity.license_number, true));
+    }
+
+    out
+}
+
+fn decrypt(value: &Option<sec_models::CipherString>, sym_key: &sec_models::SymmetricKey) -> Option<String> {
+    value.as_ref().and_then(|v| v.decrypt_string(sym_key).ok())
+}
+
+/// The value of the property a linked custom field points to, and whether it is sensitive
+pub fn linked_value(cipher: &models::Cipher, linked_id: u32, sym_key: &sec_models::SymmetricKey)
+        -> Option<(String, bool)> {
+
+    properties(cipher)
+        .into_iter()
+        .find(|p| p.linked_id == linked_id)
+        .map(|p| (decrypt(p.value, sym_key).unwrap_or_default(), p.sensitive))
+}
+
+/**
+Decrypts a custom field according to its type:
+    text - the value as is
+    hidden - the value, marked as sensitive and literal, since secrets are never interpolated
+    boolean - `true` or `false`
+    linked - the value of the login, card or identity property it points to, marked as literal
+Fields of unknown types are treated as hidden ones.
+*/
+pub fn custom_field(cipher: &models::Cipher, field: &models::CipherField, sym_key: &sec_models::SymmetricKey)
+        -> NoteField {
+
+    let name = field.name.decrypt_string(sym_key).unwrap_or_default();
+    let value = decrypt(&field.value, sym_key).unwrap_or_default();
+
+    let (value, sensitive, literal) = match field.field_type {
+        0 => (value, false, false),
+        2 => (value.trim().eq_ignore_ascii_case("true").to_string(), false, false),
+        3 => {
+            let (value, sensitive) = field.linked_id
+                .and_then(|id| linked_value(cipher, id, sym_key))
+                .unwrap_or_default();
+            (value, sensitive, true)
+        },
+        _ => (value, true, true)
+    };
+
+    NoteField { name, value, sensitive, literal }
+}
+
+/**
+The variables mapped from the login, card or identity of an item:
+    Login - USERNAME, PASSWORD, URI (the first one), URI_2, URI_3, ...
+    Card - CARDHOLDER_NAME, BRAND, NUMBER, EXP_MONTH, EXP_YEAR, CODE
+    Identity - TITLE, FIRST_NAME, MIDDLE_NAME, LAST_NAME, ADDRESS1, ADDRESS2, ADDRESS3, CITY,
+        STATE, POSTAL_CODE, COUNTRY, COMPANY, EMAIL, PHONE, SSN, USERNAME, PASSPORT_NUMBER,
+        LICENSE_NUMBER
+All of them are prefixed with `prefix` and an underscore. Empty values are left out. The values
+are marked as literal, so they are never interpolated.
+*/
+pub fn mapped_fields(cipher: &models::Cipher, prefix: &str, sym_key: &sec_models::SymmetricKey) -> Vec<NoteField> {
+    let mut out = Vec::new();
+    let mut add = |suffix: &str, value: &Option<sec_models::CipherString>, sensitive: bool| {
+        match decrypt(value, sym_key) {
+            Some(value) if !value.is_empty() => out.push(NoteField {
+                name: prefixed(prefix, suffix), value, sensitive, literal: true
+            }),
+            _ => ()
+        }
+    };
+
+    for property in properties(cipher) {
+        add(property.suffix, property.value, property.sensitive);
+
+        // the uris come right after the login's password
+        if let (101, Some(login)) = (property.linked_id, &cipher.login) {
+            for (i, uri) in login.uris.iter().flatten().enumerate() {
+                match i {
+                    0 => add("URI", &uri.uri, false),
+                    i => add(&format!("URI_{}", i + 1), &uri.uri, false),
+                }
+            }
+        }
+    }
+
+    out
+}
+
+/// The moment TOTP codes are generated for. It is fixed by the first login with a TOTP seed, so
+/// all codes are from the same moment and `wait_fresh` makes nxc wait at most once.
+pub struct TotpClock {
+    /// if the current code expires in less than this many seconds, the next one is waited for
+    wait_fresh: Option<u64>,
+    time: Cell<Option<u64>>,
+}
+
+impl TotpClock {
+    pub fn new(wait_fresh: Option<u64>) -> Self {
+        Self { wait_fresh, time: Cell::new(None) }
+    }
+
+    fn time(&self, totp: &Totp) -> Result<u64> {
+        if let Some(time) = self.time.get() {
+            return Ok(time);
+        }
+
+        let mut time = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .context("The system clock is set before 1970")?
+            .as_secs();
+
+        let remaining = totp.remaining(time);
+        if remaining < self.wait_fresh.unwrap_or(0) {
+            thread::sleep(Duration::from_secs(remaining));
+            time += remaining;
+        }
+
+        self.time.set(Some(time));
+
+        Ok(time)
+    }
+}
+
+/// The current code of the TOTP seed of a login, as `<prefix>_TOTP`
+pub fn totp_field(cipher: &models::Cipher, prefix: &str, sym_key: &sec_models::SymmetricKey, clock: &TotpClock)
+        -> Result<Option<NoteField>> {
+
+    let seed = match cipher.login.as_ref().and_then(|l| decrypt(&l.totp, sym_key)) {
+        Some(v) if !v.trim().is_empty() => v,
+        _ => return Ok(None)
+    };
+    let totp = Totp::parse(&seed)?;
+    let value = totp.code(clock.time(&totp)?)?;
+
+    Ok(Some(NoteField { name: prefixed(prefix, "TOTP"), value, sensitive: true, literal: true }))
+}
@@ -4,9 +4,12 @@ use anyhow::{bail, Context, Result};
 
 pub mod auth;
 pub mod models;
+pub mod select;
 
 mod service;
+mod attachments;
 mod constants;
+mod items;
 mod sync;
 mod utils;
 mod store;
@@ -14,8 +17,19 @@ mod store;
 use security::models::{Decrypt, self as sec_models};
 
 
-pub fn get_by_name(name: &str, token: &models::TokenResponse, ignore_conn_errors: bool, quiet: bool)
-        -> Result<HashMap<String, String>> {
+/// Checks whether the vault changed on the server since it was last synced. The next call to
+/// one of the `get_*` functions will sync it.
+pub fn vault_changed(config: &models::Config, token: &models::TokenResponse) -> Result<bool> {
+    sync::has_changes(config, token)
+}
+
+/// Decrypts the secure notes matching each of the given selectors. The vault is loaded only once
+/// and the result contains the notes found for each selector, in the same order as `selectors`.
+/// The notes for a selector are sorted alphabetically. Only the item types given in `options`
+/// are considered.
+pub fn get_notes(
+    selectors: &[select::Selector], options: &select::Options, config: &models::Config, token: &models::TokenResponse, ignore_conn_errors: bool, quiet: bool,
+) -> Result<Vec<Vec<models::Note>>> {
 
     let key = match &token.master_key {
         Some(v) => v,
@@ -28,41 +42,243 @@ pub fn get_by_name(name: &str, token: &models::TokenResponse, ignore_conn_errors
     let key: Result<sec_models::SymmetricKey> = master_key.into();
     let key = key?;
 
-    let data = sync::load_data(&token, ignore_conn_errors, quiet)?;
-    let sym_key = sec_models::SymmetricKey::from(
-        data.profile.key.decrypt(&key)?);
+    let data = sync::load_data(config, &token, ignore_conn_errors, quiet)?;
+    if !quiet {
+        report_invalid(&data);
+    }
+    let keys = Keys::load(&data, &key, quiet)?;
+    let names = Names::load(&data, &keys);
+
+    let loader = Loader {
+        options,
+        fetcher: attachments::Fetcher::new(config, token),
+        clock: items::TotpClock::new(options.wait_fresh),
+        quiet,
+    };
+
+    selectors
+        .iter()
+        .map(|selector| find_notes(selector, &data, &keys, &names, &loader))
+        .collect()
+}
+
+/// Reports the items of the vault that couldn't be read, they are skipped
+fn report_invalid(data: &models::SyncResponse) {
+    for cipher in &data.ciphers {
+        if let models::Lenient::Invalid { raw, error } = cipher {
+            eprintln!("Could not read item {}, it will be skipped: {}",
+                raw.get("Id").and_then(|v| v.as_str()).unwrap_or("<unknown>"), error);
+        }
+    }
+}
+
+/// The decrypted names of the folders and collections. The ones that can't be decrypted are left
+/// out.
+struct Names {
+    folders: HashMap<uuid::Uuid, String>,
+    collections: HashMap<uuid::Uuid, String>,
+}
+
+impl Names {
+    fn load(data: &models::SyncResponse, keys: &Keys) -> Self {
+        let folders = data.folders
+            .iter()
+            .filter_map(models::Lenient::valid)
+            .filter_map(|f| Some((f.id, f.name.decrypt_string(&keys.user).ok()?)))
+            .collect();
+
+        let collections = data.collections
+            .iter()
+            .filter_map(models::Lenient::valid)
+            .filter_map(|c| {
+                let key = keys.orgs.get(&c.organization_id)?;
+                Some((c.id, c.name.decrypt_string(key).ok()?))
+            })
+            .collect();
+
+        Self { folders, collections }
+    }
+}
+
+/// The keys the ciphers of the vault are encrypted with
+struct Keys {
+    user: sec_models::SymmetricKey,
+    orgs: HashMap<uuid::Uuid, sec_models::SymmetricKey>,
+}
+
+impl Keys {
+    /// Decrypts the user key and, with the account's private key, the keys of the organizations
+    /// the user is a member of. Organizations whose key can't be decrypted are reported and
+    /// their items skipped.
+    fn load(data: &models::SyncResponse, master_key: &sec_models::SymmetricKey, quiet: bool) -> Result<Self> {
+        let user = sec_models::SymmetricKey::from(
+            data.profile.key.decrypt(master_key)?);
+
+        let mut orgs = HashMap::new();
+        if data.profile.organizations.is_empty() {
+            return Ok(Self { user, orgs });
+        }
+
+        let private_key = data.profile.private_key
+            .as_ref()
+            .context("Could not find the private key in the profile")
+            .and_then(|v| v.decrypt(&user))
+            .and_then(|v| sec_models::PrivateKey::from_der(&v))
+            .context("Could not decrypt the private key, organization items will be skipped");
+
+        let private_key = match private_key {
+            Ok(v) => v,
+            Err(e) => {
+                if !quiet {
+                    eprintln!("{:#}", e);
+                }
+                return Ok(Self { user, orgs });
+            }
+        };
+
+        for org in &data.profile.organizations {
+            match decrypt_org_key(org, &private_key) {
+                Ok(key) => { orgs.insert(org.id, key); },
+                Err(e) => if !quiet {
+                    eprintln!("Could not decrypt the key of organization {}, its items will be skipped: {:#}",
+                        org.name.as_deref().unwrap_or(&org.id.to_string()), e);
+                }
+            }
+        }
+
+        Ok(Self { user, orgs })
+    }
+
+    /// The key a cipher is encrypted with: its item key if it has one, otherwise the user or
+    /// organization key. None if the organization key is not available.
+    fn for_cipher(&self, cipher: &models::Cipher) -> Option<Result<sec_models::SymmetricKey>> {
+        let key = match &cipher.organization_id {
+            Some(id) => self.orgs.get(id)?,
+            None => &self.user
+        };
 
-    // filter for a secure note with the specified name
-    let mut found: Vec<&models::Cipher> = data
+        Some(match &cipher.key {
+            Some(item_key) => item_key
+                .decrypt(key)
+                .and_then(symmetric_key)
+                .context("Could not decrypt item key"),
+            None => Ok(key.clone())
+        })
+    }
+}
+
+/// Makes a symmetric key out of decrypted key bytes, checking their length
+fn symmetric_key(key: Vec<u8>) -> Result<sec_models::SymmetricKey> {
+    if key.len() != 32 && key.len() != 64 {
+        bail!("Unexpected key length: {}", key.len());
+    }
+
+    Ok(sec_models::SymmetricKey::from(key))
+}
+
+fn decrypt_org_key(org: &models::ProfileOrganization, private_key: &sec_models::PrivateKey)
+        -> Result<sec_models::SymmetricKey> {
+
+    let key = org.key
+        .as_ref()
+        .context("Missing organization key")?
+        .decrypt(private_key)?;
+
+    symmetric_key(key)
+}
+
+/// What turning the matching items into notes needs, besides their keys
+struct Loader<'a> {
+    options: &'a select::Options,
+    fetcher: attachments::Fetcher<'a>,
+    clock: items::TotpClock,
+    quiet: bool,
+}
+
+fn find_notes(
+    selector: &select::Selector, data: &models::SyncResponse, keys: &Keys, names: &Names, loader: &Loader,
+) -> Result<Vec<models::Note>> {
+
+    // filter for secure notes matching the selector
+    let mut found: Vec<models::Note> = data
         .ciphers
         .iter()
-        .filter_map(
-            |c| if c.cipher_type == 2{
-                if c.name.decrypt_string(&sym_key)
-                        .unwrap_or("".to_string()) // empty string contains only empty string
-                        .to_lowercase()
-                        .contains(&name.to_lowercase()) {
-                    Some(c)
-                } else { None }
-            } else { None })
-        .collect();
-
-    found.sort_by_cached_key(|c| c.name
-        .decrypt_string(&sym_key)
-        .unwrap_or("".to_string())  // don't order strings that can't be decrypted :)
-    );
-
-    let mut env_vars: HashMap<String, String> = HashMap::new();
-    for cipher in found {
-        for field in cipher
-                .fields.as_ref()
-                .unwrap_or(&Vec::<models::CipherField>::new()) {
-
-            env_vars.insert(
-                field.name.decrypt_string(&sym_key).unwrap_or("".to_string()),
-                field.value.decrypt_string(&sym_key).unwrap_or("".to_string()));
+        .filter_map(models::Lenient::valid)
+        .filter(|c| loader.options.item_types.iter().any(|t| t.id() == c.cipher_type))
+        // items whose key can't be decrypted are skipped, like the ones whose name can't be
+        .filter_map(|c| Some((c, keys.for_cipher(c)?.ok()?)))
+        .filter_map(|(c, sym_key)| {
+            let note_name = c.name.decrypt_string(&sym_key)
+                .unwrap_or("".to_string()); // empty string contains only empty string
+
+            let item = select::Item {
+                id: &c.id,
+                name: &note_name,
+                folder: c.folder_id.as_ref().and_then(|id| names.folders.get(id)).map(|v| v.as_str()),
+                collections: c.collection_ids
+                    .iter()
+                    .filter_map(|id| names.collections.get(id))
+                    .map(|v| v.as_str())
+                    .collect(),
+            };
+
+            if selector.matches(&item) {
+                Some(decrypt_note(c, note_name, &sym_key, loader))
+            } else { None }
+        })
+        .collect::<Result<_>>()?;
+
+    // don't order strings that can't be decrypted :)
+    found.sort_by(|a, b| a.name.cmp(&b.name));
+
+    Ok(found)
+}
+
+/// Decrypts the fields of an item. The ones mapped from a login, card or identity come first,
+/// so custom fields with the same name win over them. The attachments matching the patterns in
+/// the options are downloaded. A TOTP seed that can't be used is reported and skipped.
+fn decrypt_note(cipher: &models::Cipher, name: String, sym_key: &sec_models::SymmetricKey, loader: &Loader)
+        -> Result<models::Note> {
+
+    let options = loader.options;
+
+    let prefix = match &options.prefix {
+        Some(v) => v.clone(),
+        None => items::default_prefix(&name)
+    };
+    let mut fields = items::mapped_fields(cipher, &prefix, sym_key);
+    match items::totp_field(cipher, &prefix, sym_key, &loader.clock) {
+        Ok(v) => fields.extend(v),
+        Err(e) => if !loader.quiet {
+            eprintln!("Skipping the TOTP code of \"{}\": {:#}", name, e);
+        }
+    }
+
+    fields.extend(cipher
+        .fields.as_ref()
+        .unwrap_or(&Vec::<models::CipherField>::new())
+        .iter()
+        .map(|field| items::custom_field(cipher, field, sym_key)));
+
+    // the notes of logins, cards and identities are usually free text
+    let body = match cipher.cipher_type == select::ItemType::Note.id() {
+        true => cipher.notes.as_ref().and_then(|v| v.decrypt_string(sym_key).ok()),
+        false => None
+    };
+
+    let mut attachments = Vec::new();
+    for attachment in cipher.attachments.iter().flatten() {
+        let file_name = match attachment.file_name.as_ref().and_then(|v| v.decrypt_string(sym_key).ok()) {
+            Some(v) => v,
+            None => continue
+        };
+
+        if options.attachments.iter().any(|p| p.matches(&file_name)) {
+            attachments.push(loader.fetcher
+                .fetch(cipher, attachment, file_name.clone(), sym_key)
+                .context(format!("Could not load attachment \"{}\" of \"{}\"", file_name, name))?);
         }
     }
 
-    Ok(env_vars)
+    Ok(models::Note { id: cipher.id, name, fields, body, attachments })
 }
@@ -1,10 +1,12 @@
-use std::env;
+use std::{collections::HashMap, env, path::Path};
 
 use serde::{Deserialize, Serialize};
-use anyhow::{Context, Result};
+use anyhow::{bail, Context, Result};
 
 use security::models as sec_models;
 
+use crate::constants::{CONFIG_FILENAME, DEFAULT_SERVER, DEVICE_FILENAME};
+
 
 #[derive(Deserialize, Debug)]
 pub struct PreLoginResponse {
@@ -12,14 +14,35 @@ pub struct PreLoginResponse {
     pub kdf: Option<u32>,
     #[serde(rename = "KdfIterations")]
     pub iterations: Option<u32>,
+    #[serde(rename = "KdfMemory")]
+    pub memory: Option<u32>,
+    #[serde(rename = "KdfParallelism")]
+    pub parallelism: Option<u32>,
     pub error: Option<String>,
 }
 
+impl PreLoginResponse {
+    /// The key derivation function and its parameters, as configured for the account
+    pub fn kdf(&self) -> Result<sec_models::Kdf> {
+        let iterations = self.iterations.context("Did not receive KDF iterations")?;
+
+        match self.kdf.unwrap_or(0) {
+            0 => Ok(sec_models::Kdf::Pbkdf2 { iterations }),
+            1 => Ok(sec_models::Kdf::Argon2id {
+                iterations,
+                memory: self.memory.context("Did not receive Argon2 memory")?,
+                parallelism: self.parallelism.context("Did not receive Argon2 parallelism")?,
+            }),
+            v => bail!("Unsupported KDF type: {}", v)
+        }
+    }
+}
+
 #[derive(Serialize, Deserialize, Clone, Debug)]
 pub struct BWErrorModel {
-    #[serde(rename = "Message")]
+    #[serde(rename = "Message", alias = "message")]
     pub message: Option<String>,
-    #[serde(rename = "Object")]
+    #[serde(rename = "Object", alias = "object")]
     pub object: Option<String>,
 }
 
@@ -45,10 +68,20 @@ pub struct TokenResponse {
     pub error_description: Option<String>,
     #[serde(rename = "ErrorModel")]
     pub error_model: Option<BWErrorModel>,
+    /// the enabled two-step login providers by id, sent when a second factor is required
+    #[serde(rename = "TwoFactorProviders2")]
+    pub two_factor_providers: Option<HashMap<String, Option<serde_json::Value>>>,
+    /// sent when the second factor should be remembered
+    #[serde(rename = "TwoFactorToken")]
+    pub two_factor_token: Option<String>,
 
     // additional
     pub last_saved: Option<String>,
-    pub master_key: Option<String>
+    pub master_key: Option<String>,
+    /// the identity endpoint the token was issued by. Tokens saved before it was stored are from
+    /// the default server.
+    #[serde(default)]
+    pub server: Option<String>,
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug)]
@@ -56,18 +89,39 @@ pub struct Profile {
     #[serde(rename = "Email")]
     pub email: String,
     #[serde(rename = "Key")]
-    pub key: sec_models::CipherString
+    pub key: sec_models::CipherString,
+    /// the RSA private key, encrypted with the user key
+    #[serde(rename = "PrivateKey")]
+    pub private_key: Option<sec_models::CipherString>,
+    #[serde(rename = "Organizations", default)]
+    pub organizations: Vec<ProfileOrganization>,
+}
+
+/// An organization the user is a member of
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct ProfileOrganization {
+    #[serde(rename = "Id")]
+    pub id: uuid::Uuid,
+    #[serde(rename = "Name")]
+    pub name: Option<String>,
+    /// the organization key, encrypted with the user's public key
+    #[serde(rename = "Key")]
+    pub key: Option<sec_models::CipherString>,
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug)]
 pub struct CipherField {
     #[serde(rename = "Name")]
     pub name: sec_models::CipherString,
-    // todo: write what types exist
+    /// 0 - text, 1 - hidden, 2 - boolean, 3 - linked
     #[serde(rename = "Type")]
     pub field_type: u8,
+    /// not set for linked fields, and for fields left empty
     #[serde(rename = "Value")]
-    pub value: sec_models::CipherString
+    pub value: Option<sec_models::CipherString>,
+    /// the property of the login, card or identity a linked field points to
+    #[serde(rename = "LinkedId", default)]
+    pub linked_id: Option<u32>,
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug)]
@@ -89,24 +143,259 @@ pub struct Cipher {
     #[serde(rename = "Type")]
     pub cipher_type: u8,
 
+    /// set if the cipher belongs to an organization, in which case it is encrypted with the
+    /// organization key
+    #[serde(rename = "OrganizationId")]
+    pub organization_id: Option<uuid::Uuid>,
+
+    /// the item's own key, encrypted with the user or organization key. Newer clients encrypt
+    /// the rest of the item with it.
+    #[serde(rename = "Key")]
+    pub key: Option<sec_models::CipherString>,
+
+    #[serde(rename = "FolderId")]
+    pub folder_id: Option<uuid::Uuid>,
+
+    #[serde(rename = "CollectionIds", default)]
+    pub collection_ids: Vec<uuid::Uuid>,
+
     #[serde(rename = "Fields")]
     pub fields: Option<Vec<CipherField>>,
+
+    /// the body text of the item
+    #[serde(rename = "Notes")]
+    pub notes: Option<sec_models::CipherString>,
+
+    #[serde(rename = "Login")]
+    pub login: Option<CipherLogin>,
+
+    #[serde(rename = "Card")]
+    pub card: Option<CipherCard>,
+
+    #[serde(rename = "Identity")]
+    pub identity: Option<CipherIdentity>,
+
+    #[serde(rename = "Attachments")]
+    pub attachments: Option<Vec<CipherAttachment>>,
+}
+
+/// A file attached to an item. Its contents are downloaded separately.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct CipherAttachment {
+    #[serde(rename = "Id")]
+    pub id: String,
+    #[serde(rename = "FileName")]
+    pub file_name: Option<sec_models::CipherString>,
+    /// the key the file is encrypted with, encrypted with the item key. Old attachments don't
+    /// have one and are encrypted with the item key directly.
+    #[serde(rename = "Key")]
+    pub key: Option<sec_models::CipherString>,
+    /// the size of the file in bytes, as a string
+    #[serde(rename = "Size")]
+    pub size: Option<String>,
+    /// the download link. It may expire, so a fresh one is requested before downloading.
+    #[serde(rename = "Url")]
+    pub url: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct CipherLogin {
+    #[serde(rename = "Username")]
+    pub username: Option<sec_models::CipherString>,
+    #[serde(rename = "Password")]
+    pub password: Option<sec_models::CipherString>,
+    #[serde(rename = "Uris")]
+    pub uris: Option<Vec<LoginUri>>,
+    #[serde(rename = "Totp")]
+    pub totp: Option<sec_models::CipherString>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct LoginUri {
+    #[serde(rename = "Uri")]
+    pub uri: Option<sec_models::CipherString>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct CipherCard {
+    #[serde(rename = "CardholderName")]
+    pub cardholder_name: Option<sec_models::CipherString>,
+    #[serde(rename = "Brand")]
+    pub brand: Option<sec_models::CipherString>,
+    #[serde(rename = "Number")]
+    pub number: Option<sec_models::CipherString>,
+    #[serde(rename = "ExpMonth")]
+    pub exp_month: Option<sec_models::CipherString>,
+    #[serde(rename = "ExpYear")]
+    pub exp_year: Option<sec_models::CipherString>,
+    #[serde(rename = "Code")]
+    pub code: Option<sec_models::CipherString>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct CipherIdentity {
+    #[serde(rename = "Title")]
+    pub title: Option<sec_models::CipherString>,
+    #[serde(rename = "FirstName")]
+    pub first_name: Option<sec_models::CipherString>,
+    #[serde(rename = "MiddleName")]
+    pub middle_name: Option<sec_models::CipherString>,
+    #[serde(rename = "LastName")]
+    pub last_name: Option<sec_models::CipherString>,
+    #[serde(rename = "Address1")]
+    pub address1: Option<sec_models::CipherString>,
+    #[serde(rename = "Address2")]
+    pub address2: Option<sec_models::CipherString>,
+    #[serde(rename = "Address3")]
+    pub address3: Option<sec_models::CipherString>,
+    #[serde(rename = "City")]
+    pub city: Option<sec_models::CipherString>,
+    #[serde(rename = "State")]
+    pub state: Option<sec_models::CipherString>,
+    #[serde(rename = "PostalCode")]
+    pub postal_code: Option<sec_models::CipherString>,
+    #[serde(rename = "Country")]
+    pub country: Option<sec_models::CipherString>,
+    #[serde(rename = "Company")]
+    pub company: Option<sec_models::CipherString>,
+    #[serde(rename = "Email")]
+    pub email: Option<sec_models::CipherString>,
+    #[serde(rename = "Phone")]
+    pub phone: Option<sec_models::CipherString>,
+    #[serde(rename = "SSN")]
+    pub ssn: Option<sec_models::CipherString>,
+    #[serde(rename = "Username")]
+    pub username: Option<sec_models::CipherString>,
+    #[serde(rename = "PassportNumber")]
+    pub passport_number: Option<sec_models::CipherString>,
+    #[serde(rename = "LicenseNumber")]
+    pub license_number: Option<sec_models::CipherString>,
 }
 
+/// A decrypted custom field of a secure note
+#[derive(Clone, Debug)]
+pub struct NoteField {
+    pub name: String,
+    pub value: String,
+    /// whether the value is hidden in Bitwarden, and so should not be shown
+    pub sensitive: bool,
+    /// set for the properties of logins, cards and identities, whose values (such as generated
+    /// passwords) must never be interpolated
+    pub literal: bool,
+}
+
+/// A decrypted item, with its fields in the order they appear in Bitwarden. For items other than
+/// secure notes, the fields start with the ones mapped from the login, card or identity.
+#[derive(Clone, Debug)]
+pub struct Note {
+    pub id: uuid::Uuid,
+    pub name: String,
+    pub fields: Vec<NoteField>,
+    /// the decrypted body text of a secure note, if there is one. Not set for other item types.
+    pub body: Option<String>,
+    /// the decrypted attachments whose file names match the patterns in the options
+    pub attachments: Vec<NoteAttachment>,
+}
+
+#[derive(Clone, Debug)]
+pub struct NoteAttachment {
+    pub id: String,
+    pub file_name: String,
+    pub data: Vec<u8>,
+}
+
+/// The download link of an attachment
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct AttachmentResponse {
+    #[serde(rename = "Url", alias = "url")]
+    pub url: String,
+}
+
+
+/**
+An entry of a list in the sync response, read on its own: one that can't be read, e.g. because of
+an unknown encryption type, doesn't fail the whole sync. It is kept as it was received, so the
+cached vault still contains it.
+*/
+#[derive(Clone, Debug)]
+pub enum Lenient<T> {
+    Valid(T),
+    Invalid { raw: serde_json::Value, error: String },
+}
+
+impl<T> Lenient<T> {
+    pub fn valid(&self) -> Option<&T> {
+        match self {
+            Self::Valid(v) => Some(v),
+            Self::Invalid { .. } => None
+        }
+    }
+}
+
+impl<T: Serialize> Serialize for Lenient<T> {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where S: serde::Serializer {
+        match self {
+            Self::Valid(v) => v.serialize(serializer),
+            Self::Invalid { raw, .. } => raw.serialize(serializer)
+        }
+    }
+}
+
+impl<'de, T: serde::de::DeserializeOwned> Deserialize<'de> for Lenient<T> {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where D: serde::Deserializer<'de> {
+        let raw = serde_json::Value::deserialize(deserializer)?;
+
+        Ok(match T::deserialize(&raw) {
+            Ok(v) => Self::Valid(v),
+            Err(e) => Self::Invalid { error: e.to_string(), raw }
+        })
+    }
+}
 
 #[derive(Serialize, Deserialize, Clone, Debug)]
 pub struct SyncResponse {
     pub rev_date: Option<chrono::DateTime<chrono::Utc>>,
+    /// the version of this struct the cached copy was stored with. Caches from before it existed
+    /// deserialize to 0.
+    #[serde(default)]
+    pub version: u32,
+    /// the api endpoint the vault was synced from
+    #[serde(default)]
+    pub server: Option<String>,
     #[serde(rename = "Profile")]
     pub profile: Profile,
     #[serde(rename = "Ciphers")]
-    pub ciphers: Vec<Cipher>,
+    pub ciphers: Vec<Lenient<Cipher>>,
     #[serde(rename = "Collections")]
-    pub collections: Vec<serde_json::Value>,
+    pub collections: Vec<Lenient<Collection>>,
     #[serde(rename = "Domains")]
     pub domains: serde_json::Value,
     #[serde(rename = "Folders")]
-    pub folders: Vec<serde_json::Value>,
+    pub folders: Vec<Lenient<Folder>>,
+}
+
+/// A personal folder. Its name is encrypted with the user key and nested folders are separated
+/// by `/` in their names.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Folder {
+    #[serde(rename = "Id")]
+    pub id: uuid::Uuid,
+    #[serde(rename = "Name")]
+    pub name: sec_models::CipherString,
+}
+
+/// An organization collection. Its name is encrypted with the organization key and nested
+/// collections are separated by `/` in their names.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Collection {
+    #[serde(rename = "Id")]
+    pub id: uuid::Uuid,
+    #[serde(rename = "OrganizationId")]
+    pub organization_id: uuid::Uuid,
+    #[serde(rename = "Name")]
+    pub name: sec_models::CipherString,
 }
 
 #[derive(Serialize, Deserialize, Debug)]
@@ -122,6 +411,22 @@ pub struct RefreshTokenRequest {
     pub refresh_token: String
 }
 
+/// Login with a personal API key. The response has no refresh token, so the same key is used
+/// to log in again once the access token expires.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct ApiKeyTokenRequest {
+    pub grant_type: String,
+    pub scope: String,
+    pub client_id: String,
+    pub client_secret: String,
+    #[serde(rename = "deviceType")]
+    pub device_type: u8,
+    #[serde(rename = "deviceIdentifier")]
+    pub device_id: uuid::Uuid,
+    #[serde(rename = "deviceName")]
+    pub device_name: String,
+}
+
 // todo: implement defaults for this
 #[derive(Serialize, Deserialize, Debug)]
 pub struct TokenRequest {
@@ -133,41 +438,106 @@ pub struct TokenRequest {
     #[serde(rename = "deviceType")]
     pub device_type: u8,
     #[serde(rename = "deviceIdentifier")]
-    pub device_id: uuid::Uuid,  // todo: generate this into a config file
+    pub device_id: uuid::Uuid,
     #[serde(rename = "deviceName")]
-    pub device_name: String,  // todo: fetch this from config
-    #[serde(rename = "twoFactorToken")]
-    pub two_factor_token: u32,
-    #[serde(rename = "twoFactorProvider")]
-    pub two_factor_provider: u8,
-    #[serde(rename = "twoFactorRemember")]
-    pub two_factor_remember: u8
+    pub device_name: String,
+    #[serde(rename = "twoFactorToken", skip_serializing_if = "Option::is_none")]
+    pub two_factor_token: Option<String>,
+    #[serde(rename = "twoFactorProvider", skip_serializing_if = "Option::is_none")]
+    pub two_factor_provider: Option<u8>,
+    #[serde(rename = "twoFactorRemember", skip_serializing_if = "Option::is_none")]
+    pub two_factor_remember: Option<u8>
+}
+
+/// Two-step login methods, numbered as the server expects them
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum TwoFactorProvider {
+    Authenticator = 0,
+    Email = 1,
+    Duo = 2,
+    YubiKey = 3,
+    /// the token returned by an earlier login on this device
+    Remember = 5,
+}
+
+impl TwoFactorProvider {
+    /// The providers a code can be entered for, in the order they are offered
+    pub const PROMPTABLE: [Self; 4] = [Self::Authenticator, Self::Email, Self::YubiKey, Self::Duo];
+
+    pub fn id(self) -> u8 {
+        self as u8
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Authenticator => "authenticator",
+            Self::Email => "email",
+            Self::Duo => "duo",
+            Self::YubiKey => "yubikey",
+            Self::Remember => "remember",
+        }
+    }
+
+    pub fn description(self) -> &'static str {
+        match self {
+            Self::Authenticator => "Authenticator app",
+            Self::Email => "Email",
+            Self::Duo => "Duo passcode",
+            Self::YubiKey => "YubiKey OTP",
+            Self::Remember => "Remembered device",
+        }
+    }
+}
+
+/// The second factor sent along with a login
+pub struct TwoFactor {
+    pub provider: TwoFactorProvider,
+    pub token: String,
+    /// ask the server for a token that skips two-step login on this device next time
+    pub remember: bool,
+}
+
+#[derive(Serialize, Debug)]
+pub struct SendEmailLoginRequest {
+    pub email: String,
+    #[serde(rename = "masterPasswordHash")]
+    pub master_password_hash: String,
+    #[serde(rename = "deviceIdentifier")]
+    pub device_id: uuid::Uuid,
 }
 
 #[derive(Clone, Debug)]
 pub struct Config {
     pub config_dir: String,
+    pub server: ServerSettings,
+    pub device: Device,
     pub session_key: sec_models::SymmetricKey,
     pub bw_user: Option<String>,
     pub bw_pass: Option<String>,
-    pub bw_tfa: Option<String>
+    pub bw_tfa: Option<String>,
+    pub bw_tfa_provider: Option<String>,
+    pub bw_client_id: Option<String>,
+    pub bw_client_secret: Option<String>,
 }
 
 impl Config {
     pub fn load(reset_session: bool) -> Result<Self> {
-        Ok(Self {
-            config_dir: {
-                let config_dir = match env::var("NXCMDR_CONFIG_DIR") {
-                    Ok(v) => v,
-                    Err(_) => format!("{}/.config/nxcmdr", env::var("HOME")
-                        .context("HOME environment variable is not set")?)
-                };
+        let config_dir = match env::var("NXCMDR_CONFIG_DIR") {
+            Ok(v) => v,
+            Err(_) => format!("{}/.config/nxcmdr", env::var("HOME")
+                .context("HOME environment variable is not set")?)
+        };
 
-                std::fs::create_dir_all(&config_dir)
-                    .context("Could not create config directory")?;
+        std::fs::create_dir_all(&config_dir)
+            .context("Could not create config directory")?;
 
-                config_dir
-            },
+        let file = ConfigFile::load(&config_dir)?;
+        let device_name = env::var("NXCMDR_BW_DEVICE_NAME").ok().or(file.device_name);
+
+        Ok(Self {
+            server: ServerSettings::from_env().or(file.server),
+            device: Device::load(&config_dir, device_name)?,
+            config_dir,
             session_key: {
                 let skip_session_gen = env::var("NXCMDR_SKIP_SESSION_GEN").is_ok();
 
@@ -193,7 +563,165 @@ impl Config {
             },
             bw_user: env::var("NXCMDR_BW_USER").ok(),
             bw_pass: env::var("NXCMDR_BW_PASS").ok(),
-            bw_tfa: env::var("NXCMDR_BW_TFA").ok()
+            bw_tfa: env::var("NXCMDR_BW_TFA").ok(),
+            bw_tfa_provider: env::var("NXCMDR_BW_TFA_PROVIDER").ok(),
+            bw_client_id: env::var("NXCMDR_BW_CLIENT_ID").ok(),
+            bw_client_secret: env::var("NXCMDR_BW_CLIENT_SECRET").ok(),
         })
     }
 }
+
+/// How this installation identifies itself to the server. Bitwarden lists it under the account's
+/// devices and in login notifications.
+#[derive(Clone, Debug)]
+pub struct Device {
+    pub id: uuid::Uuid,
+    pub name: String,
+    pub device_type: u8,
+}
+
+impl Device {
+    /// Loads the device id from the config directory, generating it on the first run
+    pub fn load(config_dir: &str, name: Option<String>) -> Result<Self> {
+        let path = Path::new(config_dir).join(DEVICE_FILENAME);
+        let path_str = path.to_str().unwrap_or("<unknown>");
+
+        let id = match path.exists() {
+            true => std::fs::read_to_string(&path)
+                .context(format!("Could not read {}", path_str))?
+                .trim()
+                .parse::<uuid::Uuid>()
+                .context(format!("Could not parse device id from {}", path_str))?,
+            false => {
+                let id = uuid::Uuid::new_v4();
+                std::fs::write(&path, id.to_string())
+                    .context(format!("Could not write {}", path_str))?;
+                id
+            }
+        };
+
+        let name = name.unwrap_or_else(|| format!(
+            "nxcmdr@{}",
+            hostname::get().ok().and_then(|v| v.into_string().ok()).unwrap_or_else(|| "unknown".to_string())));
+
+        Ok(Self { id, name, device_type: Self::cli_type() })
+    }
+
+    /// The device type of the official CLI on this OS
+    fn cli_type() -> u8 {
+        if cfg!(target_os = "windows") {
+            23
+        } else if cfg!(target_os = "macos") {
+            24
+        } else {
+            25
+        }
+    }
+}
+
+/// Settings read from `config.json` in the config directory. Unlike the other files stored there,
+/// it is not encrypted and meant to be edited by hand.
+#[derive(Deserialize, Default, Debug)]
+pub struct ConfigFile {
+    #[serde(flatten)]
+    pub server: ServerSettings,
+    pub device_name: Option<String>,
+}
+
+impl ConfigFile {
+    pub fn load(config_dir: &str) -> Result<Self> {
+        let path = Path::new(config_dir).join(CONFIG_FILENAME);
+
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+
+        let contents = std::fs::read_to_string(&path)
+            .context(format!("Could not read {}", path.to_str().unwrap_or("<unknown>")))?;
+
+        serde_json::from_str(&contents)
+            .context(format!("Could not parse {}", path.to_str().unwrap_or("<unknown>")))
+    }
+}
+
+/// The Bitwarden server to use, as given in one place (command line, environment or config
+/// file). The endpoints are derived from `server` unless they are set explicitly.
+#[derive(Deserialize, Default, Clone, Debug)]
+pub struct ServerSettings {
+    pub server: Option<String>,
+    pub api_url: Option<String>,
+    pub identity_url: Option<String>,
+}
+
+impl ServerSettings {
+    fn from_env() -> Self {
+        Self {
+            server: env::var("NXCMDR_BW_SERVER").ok(),
+            api_url: env::var("NXCMDR_BW_API_URL").ok(),
+            identity_url: env::var("NXCMDR_BW_IDENTITY_URL").ok(),
+        }
+    }
+
+    fn is_set(&self) -> bool {
+        self.server.is_some() || self.api_url.is_some() || self.identity_url.is_some()
+    }
+
+    /// Takes all the settings from `other` if none are set here. They are never mixed, so the api
+    /// and identity endpoints always belong to the same server.
+    pub fn or(self, other: Self) -> Self {
+        match self.is_set() {
+            true => self,
+            false => other
+        }
+    }
+
+    pub fn urls(&self) -> Urls {
+        let base = self.server.as_deref()
+            .unwrap_or(DEFAULT_SERVER)
+            .trim_end_matches('/');
+
+        Urls {
+            api: match &self.api_url {
+                Some(v) => v.trim_end_matches('/').to_string(),
+                None => format!("{}/api", base)
+            },
+            identity: match &self.identity_url {
+                Some(v) => v.trim_end_matches('/').to_string(),
+                None => format!("{}/identity", base)
+            },
+        }
+    }
+}
+
+/// The endpoints of a Bitwarden server
+#[derive(Clone, Debug)]
+pub struct Urls {
+    pub api: String,
+    pub identity: String,
+}
+
+impl Urls {
+    pub fn prelogin(&self) -> String {
+        format!("{}/accounts/prelogin", self.api)
+    }
+
+    pub fn token(&self) -> String {
+        format!("{}/connect/token", self.identity)
+    }
+
+    pub fn sync(&self) -> String {
+        format!("{}/sync", self.api)
+    }
+
+    pub fn revision(&self) -> String {
+        format!("{}/accounts/revision-date", self.api)
+    }
+
+    pub fn send_email_login(&self) -> String {
+        format!("{}/two-factor/send-email-login", self.api)
+    }
+
+    pub fn attachment(&self, cipher_id: &uuid::Uuid, attachment_id: &str) -> String {
+        format!("{}/ciphers/{}/attachment/{}", self.api, cipher_id, attachment_id)
+    }
+}
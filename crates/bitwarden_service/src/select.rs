@@ -0,0 +1,151 @@
+use std::str::FromStr;
+
+use anyhow::{anyhow, Context, Error, Result};
+
+
+/// How a secure note is selected by the given name
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum MatchMode {
+    /// the note name contains the value, ignoring case
+    Contains,
+    Exact,
+    Prefix,
+    Glob,
+    Regex,
+    /// the value is the UUID of the note
+    Id,
+}
+
+pub const MATCH_MODE_NAMES: [&str; 6] = ["contains", "exact", "prefix", "glob", "regex", "id"];
+
+impl FromStr for MatchMode {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(match s {
+            "contains" => Self::Contains,
+            "exact" => Self::Exact,
+            "prefix" => Self::Prefix,
+            "glob" => Self::Glob,
+            "regex" => Self::Regex,
+            "id" => Self::Id,
+            v => return Err(anyhow!("Unknown match mode: {} (expected one of: {})", v, MATCH_MODE_NAMES.join(", ")))
+        })
+    }
+}
+
+/// The kinds of items variables can be loaded from
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ItemType {
+    Login = 1,
+    Note = 2,
+    Card = 3,
+    Identity = 4,
+}
+
+pub const ITEM_TYPE_NAMES: [&str; 4] = ["note", "login", "card", "identity"];
+
+impl ItemType {
+    pub fn id(self) -> u8 {
+        self as u8
+    }
+}
+
+impl FromStr for ItemType {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(match s {
+            "note" => Self::Note,
+            "login" => Self::Login,
+            "card" => Self::Card,
+            "identity" => Self::Identity,
+            v => return Err(anyhow!("Unknown item type: {} (expected one of: {})", v, ITEM_TYPE_NAMES.join(", ")))
+        })
+    }
+}
+
+/// Which items are loaded and how they are turned into variables
+#[derive(Clone, Debug)]
+pub struct Options {
+    pub item_types: Vec<ItemType>,
+    /// prefix of the variables mapped from logins, cards and identities. Derived from the item
+    /// name if not set.
+    pub prefix: Option<String>,
+    /// if the current TOTP code of a login expires in less than this many seconds, the next one
+    /// is waited for
+    pub wait_fresh: Option<u64>,
+    /// the attachments that are downloaded, by file name
+    pub attachments: Vec<glob::Pattern>,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Self { item_types: vec![ItemType::Note], prefix: None, wait_fresh: None, attachments: Vec::new() }
+    }
+}
+
+/// The decrypted properties of an item that selectors look at
+pub struct Item<'a> {
+    pub id: &'a uuid::Uuid,
+    pub name: &'a str,
+    pub folder: Option<&'a str>,
+    pub collections: Vec<&'a str>,
+}
+
+/// Selects secure notes by name, id, folder or collection
+#[derive(Clone, Debug)]
+pub enum Selector {
+    Contains(String),
+    Exact(String),
+    Prefix(String),
+    Glob(glob::Pattern),
+    Regex(regex::Regex),
+    Id(uuid::Uuid),
+    /// the folder name, and whether the folders nested in it are included
+    Folder(String, bool),
+    /// the collection name, and whether the collections nested in it are included
+    Collection(String, bool),
+}
+
+/// Checks a folder or collection name, where nested ones are separated by `/`
+fn in_tree(name: &str, root: &str, recursive: bool) -> bool {
+    name == root || (recursive && name.starts_with(root) && name[root.len()..].starts_with('/'))
+}
+
+impl Selector {
+    pub fn new(mode: MatchMode, value: &str) -> Result<Self> {
+        Ok(match mode {
+            MatchMode::Contains => Self::Contains(value.to_lowercase()),
+            MatchMode::Exact => Self::Exact(value.to_string()),
+            MatchMode::Prefix => Self::Prefix(value.to_string()),
+            MatchMode::Glob => Self::Glob(glob::Pattern::new(value)
+                .context(format!("Invalid glob pattern: {}", value))?),
+            MatchMode::Regex => Self::Regex(regex::Regex::new(value)
+                .context(format!("Invalid regular expression: {}", value))?),
+            MatchMode::Id => Self::Id(value.parse::<uuid::Uuid>()
+                .map_err(|_| anyhow!("Invalid note id: {}", value))?),
+        })
+    }
+
+    pub fn folder(name: &str, recursive: bool) -> Self {
+        Self::Folder(name.trim_end_matches('/').to_string(), recursive)
+    }
+
+    pub fn collection(name: &str, recursive: bool) -> Self {
+        Self::Collection(name.trim_end_matches('/').to_string(), recursive)
+    }
+
+    pub fn matches(&self, item: &Item) -> bool {
+        match self {
+            Self::Contains(v) => item.name.to_lowercase().contains(v),
+            Self::Exact(v) => item.name == v,
+            Self::Prefix(v) => item.name.starts_with(v.as_str()),
+            Self::Glob(v) => v.matches(item.name),
+            Self::Regex(v) => v.is_match(item.name),
+            Self::Id(v) => item.id == v,
+            Self::Folder(v, recursive) => item.folder.map_or(false, |f| in_tree(f, v, *recursive)),
+            Self::Collection(v, recursive) => item.collections.iter().any(|c| in_tree(c, v, *recursive)),
+        }
+    }
+}
@@ -1,10 +1,10 @@
 use reqwest;
 use chrono::{Utc, TimeZone};
-use anyhow::{Context, Result, anyhow, bail};
+use anyhow::{Context, Result, bail};
 
 use security::models as sec_models;
 
-use crate::{constants::*, models};
+use crate::{constants, models::{self, Device, Urls}};
 
 fn make_get_request(url: &str, token: &models::TokenResponse) -> Result<reqwest::blocking::Response> {
     let client = reqwest::blocking::Client::new();
@@ -20,45 +20,50 @@ fn make_get_request(url: &str, token: &models::TokenResponse) -> Result<reqwest:
         .context(format!("Request failed: {}", url))?)
 }
 
-pub fn get_full_sync(token: &models::TokenResponse) -> Result<models::SyncResponse> {
-    let rev_date = get_revision_date(token)?;
-    let mut res = make_get_request(&crate::SYNC_URL!(), &token)?
+pub fn get_full_sync(urls: &Urls, token: &models::TokenResponse) -> Result<models::SyncResponse> {
+    let rev_date = get_revision_date(urls, token)?;
+    let mut res = make_get_request(&urls.sync(), &token)?
         .json::<models::SyncResponse>()?;
 
     res.rev_date = Some(rev_date);
+    res.version = constants::DATA_VERSION;
+    res.server = Some(urls.api.clone());
 
     Ok(res)
 }
 
-pub fn get_revision_date(token: &models::TokenResponse) -> Result<chrono::DateTime<Utc>> {
-    let res = make_get_request(&crate::REVISION_URL!(), token)?
+pub fn get_revision_date(urls: &Urls, token: &models::TokenResponse) -> Result<chrono::DateTime<Utc>> {
+    let res = make_get_request(&urls.revision(), token)?
         .json::<i64>()?;
 
     Ok(Utc.timestamp(res / 1000, 0))
 }
 
-pub fn get_iterations(email: &str) -> Result<u32> {
+/// Gets the key derivation function the account uses
+pub fn get_kdf(urls: &Urls, email: &str) -> Result<sec_models::Kdf> {
     let payload = models::IterationsRequest {
         email: email.to_string()
     };
 
     let client = reqwest::blocking::Client::new();
     let res = client
-        .post(&crate::PRELOGIN_URL!())
+        .post(&urls.prelogin())
         .json(&payload)
         .send()?
         .json::<models::PreLoginResponse>()?;
 
     match &res.error {
-        Some(e) => bail!("Could not retrieve iterations: {}", e),
+        Some(e) => bail!("Could not retrieve KDF settings: {}", e),
         _ => ()
     };
 
-    res.iterations.ok_or(anyhow!("Did not receive iterations for email: {}", email))
+    res.kdf().context(format!("Could not retrieve KDF settings for email: {}", email))
 }
 
+/// Logs in with the master password. If the server asks for a second factor, the response is
+/// returned with `two_factor_providers` set instead of failing.
 pub fn get_new_token(
-    email: &str, master_key: &sec_models::MasterKey, tfa_code: &str,
+    urls: &Urls, device: &Device, email: &str, master_key: &sec_models::MasterKey, two_factor: Option<&models::TwoFactor>,
 ) -> Result<models::TokenResponse> {
 
     let client = reqwest::blocking::Client::new();
@@ -69,21 +74,24 @@ pub fn get_new_token(
         password: master_key.hash.clone(),
         scope: "api offline_access".into(),
         client_id: "web".into(),
-        device_type: 10,
-        // todo: fetch from config
-        device_id: uuid::Uuid::parse_str("7d52408d-883d-4ed1-8dbb-fc6ff1a16c38").unwrap(),
-        device_name: "firefox".into(),
-        two_factor_token: tfa_code.parse::<u32>().context("TFA code was not a number")?,
-        two_factor_provider: 0,
-        two_factor_remember: 0,
+        device_type: device.device_type,
+        device_id: device.id,
+        device_name: device.name.clone(),
+        two_factor_token: two_factor.map(|v| v.token.clone()),
+        two_factor_provider: two_factor.map(|v| v.provider.id()),
+        two_factor_remember: two_factor.map(|v| v.remember as u8),
     };
 
     let mut res = client
-        .post(&crate::TOKEN_URL!())
+        .post(&urls.token())
         .form(&payload)
         .send()?
         .json::<models::TokenResponse>()?;
 
+    if res.two_factor_providers.is_some() {
+        return Ok(res);
+    }
+
     res.last_saved = Some(chrono::offset::Local::now().to_string());
     res.master_key = Some(base64::encode(&master_key.key));
 
@@ -100,7 +108,99 @@ pub fn get_new_token(
     Ok(res)
 }
 
-pub fn refresh_token(token: &mut models::TokenResponse) -> Result<()> {
+/// Asks the server to send a two-step login code to the account's email address
+pub fn send_email_login(urls: &Urls, device: &Device, email: &str, master_key: &sec_models::MasterKey) -> Result<()> {
+    let client = reqwest::blocking::Client::new();
+
+    let payload = models::SendEmailLoginRequest {
+        email: email.into(),
+        master_password_hash: master_key.hash.clone(),
+        device_id: device.id,
+    };
+
+    let res = client
+        .post(&urls.send_email_login())
+        .json(&payload)
+        .send()?;
+
+    if !res.status().is_success() {
+        let message = res
+            .json::<models::BWErrorModel>()
+            .ok()
+            .and_then(|e| e.message)
+            .unwrap_or_else(|| "Unknown error".to_string());
+        bail!("Could not send the login code by email: {}", message);
+    }
+
+    Ok(())
+}
+
+/// Gets a fresh download link for an attachment
+pub fn get_attachment_url(urls: &Urls, token: &models::TokenResponse, cipher_id: &uuid::Uuid, attachment_id: &str)
+        -> Result<String> {
+
+    let res = make_get_request(&urls.attachment(cipher_id, attachment_id), token)?;
+
+    if !res.status().is_success() {
+        bail!("Could not get the download link of attachment {}: {}", attachment_id, res.status());
+    }
+
+    Ok(res.json::<models::AttachmentResponse>()?.url)
+}
+
+/// Downloads the encrypted contents of an attachment. The link is usually to a storage service
+/// and already carries its own authorization, so no token is sent.
+pub fn download_attachment(url: &str) -> Result<Vec<u8>> {
+    let res = reqwest::blocking::get(url)
+        .context("Could not download attachment")?;
+
+    if !res.status().is_success() {
+        bail!("Could not download attachment: {}", res.status());
+    }
+
+    Ok(res.bytes()?.to_vec())
+}
+
+/// Logs in with a personal API key. The returned token does not contain the master key, as the
+/// server does not need the master password for this.
+pub fn get_api_key_token(urls: &Urls, device: &Device, client_id: &str, client_secret: &str) -> Result<models::TokenResponse> {
+    let client = reqwest::blocking::Client::new();
+
+    let payload = models::ApiKeyTokenRequest {
+        grant_type: "client_credentials".into(),
+        scope: "api".into(),
+        client_id: client_id.into(),
+        client_secret: client_secret.into(),
+        device_type: device.device_type,
+        device_id: device.id,
+        device_name: device.name.clone(),
+    };
+
+    let mut res = client
+        .post(&urls.token())
+        .form(&payload)
+        .send()?
+        .json::<models::TokenResponse>()?;
+
+    match (&res.error_model, &res.error) {
+        (Some(e), _) => bail!(
+            "Could not retrieve token: {}",
+            match &e.message {
+                Some(v) => v.clone(),
+                None => "Unknown error".to_string()
+            }),
+        (None, Some(e)) => bail!(
+            "Could not retrieve token: {}",
+            res.error_description.as_ref().unwrap_or(e)),
+        _ => ()
+    };
+
+    res.last_saved = Some(chrono::offset::Local::now().to_string());
+
+    Ok(res)
+}
+
+pub fn refresh_token(urls: &Urls, token: &mut models::TokenResponse) -> Result<()> {
     let client = reqwest::blocking::Client::new();
     let refresh_token = match &token.refresh_token {
         Some(v) => v,
@@ -113,7 +213,7 @@ pub fn refresh_token(token: &mut models::TokenResponse) -> Result<()> {
     };
 
     let mut res = client
-        .post(&crate::TOKEN_URL!())
+        .post(&urls.token())
         .form(&payload)
         .send()?
         .json::<models::TokenResponse>()?;
@@ -10,6 +10,11 @@ use crate::models::Config;
 
 pub fn store_data<T: serde::Serialize>(path: &Path, value: T) -> Result<()>{
     let config = Config::load(false)?;
+    store_data_with(path, value, &config.session_key)
+}
+
+/// Like `store_data`, but encrypts the data with `key` instead of the session key
+pub fn store_data_with<T: serde::Serialize>(path: &Path, value: T, key: &sec_models::SymmetricKey) -> Result<()> {
     let path = Path::new(path);
     let path_str = path.to_str().unwrap_or("");
 
@@ -19,7 +24,7 @@ pub fn store_data<T: serde::Serialize>(path: &Path, value: T) -> Result<()>{
 
     let data = serde_json::to_string(&value)?;
     let data = Vec::<u8>::from(data);
-    let data = config.session_key.encrypt(&data)?;
+    let data = key.encrypt(&data)?;
 
     File::create(path)
         .context(format!("Could not create file {}", path_str))?
@@ -33,6 +38,13 @@ pub fn load_stored<T>(path: &Path) -> Result<T>
     where T: serde::de::DeserializeOwned
 {
     let config = Config::load(false)?;
+    load_stored_with(path, &config.session_key)
+}
+
+/// Like `load_stored`, for data encrypted with `key` instead of the session key
+pub fn load_stored_with<T>(path: &Path, key: &sec_models::SymmetricKey) -> Result<T>
+    where T: serde::de::DeserializeOwned
+{
     let mut data = String::new();
     let path_str = path.to_str().unwrap_or("");
 
@@ -43,7 +55,7 @@ pub fn load_stored<T>(path: &Path) -> Result<T>
 
     let data_wrapper = sec_models::StringWrapper::from(data.as_str());
     let data_cs: Result<sec_models::CipherString> = (&data_wrapper).into();
-    let data = data_cs?.decrypt(&config.session_key)?;
+    let data = data_cs?.decrypt(key)?;
 
     let data = String::from_utf8(data)?;
     let data = serde_json::from_str(&data)?;
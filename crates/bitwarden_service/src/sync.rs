@@ -6,33 +6,56 @@ use crate::{constants, models, service, store, utils::process_conn_errors};
 
 
 
-fn needs_sync(token: &models::TokenResponse, data: &models::SyncResponse) -> Result<bool> {
+fn needs_sync(urls: &models::Urls, token: &models::TokenResponse, data: &models::SyncResponse) -> Result<bool> {
     Ok(match data.rev_date {
-        Some(v) => v < service::get_revision_date(token)?,
+        Some(v) => v < service::get_revision_date(urls, token)?,
         None => true
     })
 }
 
-pub fn load_data(token: &models::TokenResponse, ignore_conn_errors: bool, quiet: bool) -> anyhow::Result<models::SyncResponse> {
-    let config = models::Config::load(false)?;
+/// The cached vault, unless it was stored by a version that cached fewer fields or synced from
+/// another server
+fn load_cached(path: &Path, urls: &models::Urls) -> Option<models::SyncResponse> {
+    store::load_stored::<models::SyncResponse>(path)
+        .ok()
+        .filter(|data| data.version == constants::DATA_VERSION)
+        .filter(|data| data.server.as_deref() == Some(urls.api.as_str()))
+}
+
+/// Checks whether the vault changed on the server since the cached copy was synced
+pub fn has_changes(config: &models::Config, token: &models::TokenResponse) -> Result<bool> {
+    let path = Path::new(&config.config_dir)
+        .join(constants::DATA_FILENAME);
+
+    let urls = config.server.urls();
+
+    match load_cached(&path, &urls) {
+        Some(data) => needs_sync(&urls, token, &data),
+        None => Ok(true)
+    }
+}
+
+pub fn load_data(config: &models::Config, token: &models::TokenResponse, ignore_conn_errors: bool, quiet: bool)
+        -> anyhow::Result<models::SyncResponse> {
+    let urls = config.server.urls();
     let path = Path::new(&config.config_dir)
         .join(constants::DATA_FILENAME);
 
-    let initial = store::load_stored::<models::SyncResponse>(&path).ok();
+    let initial = load_cached(&path, &urls);
     let mut data: models::SyncResponse;
 
     match initial {
         Some(v) => data = v,
         None => {
-            data = service::get_full_sync(&token)?.into();
+            data = service::get_full_sync(&urls, &token)?.into();
             store::store_data(&path, &data)?;
             return Ok(data);
         }
     }
 
     if process_conn_errors(
-            needs_sync(&token, &data), false, ignore_conn_errors, quiet)? {
-        data = service::get_full_sync(&token)?;
+            needs_sync(&urls, &token, &data), false, ignore_conn_errors, quiet)? {
+        data = service::get_full_sync(&urls, &token)?;
         store::store_data(&path, &data)?;
     }
 
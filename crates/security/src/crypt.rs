@@ -1,9 +1,11 @@
 use std::iter::repeat;
 
 use rand_core::{OsRng, RngCore};
-use sha2::Sha256;
+use sha1::Sha1;
+use sha2::{Digest, Sha256};
+use rsa::PaddingScheme;
 use hmac::{Hmac, Mac, NewMac};
-use aes::Aes256;
+use aes::{Aes128, Aes256};
 use block_modes::{BlockMode, Cbc};
 use block_modes::block_padding::Pkcs7;
 use anyhow::{Result, bail, Context};
@@ -13,6 +15,7 @@ use crate::models;
 
 type HmacSha256 = Hmac<Sha256>;
 type Aes256Cbc = Cbc<Aes256, Pkcs7>;
+type Aes128Cbc = Cbc<Aes128, Pkcs7>;
 
 
 fn hkdf_expand(key: &[u8], info: &str) -> Result<Vec<u8>> {
@@ -77,15 +80,78 @@ pub fn generate_pbkdf(password: &[u8], salt: &[u8], iterations: u32) -> Vec<u8>
     output
 }
 
+/// Derives a key with Argon2id. Like Bitwarden, the salt is hashed with SHA256 first, as Argon2
+/// needs a salt of fixed length. `memory` is in MiB.
+pub fn generate_argon2id(password: &[u8], salt: &[u8], iterations: u32, memory: u32, parallelism: u32)
+        -> Result<Vec<u8>> {
+
+    let salt = Sha256::digest(salt);
+
+    let config = argon2::Config {
+        variant: argon2::Variant::Argon2id,
+        version: argon2::Version::Version13,
+        mem_cost: memory.checked_mul(1024).context("Argon2 memory is too large")?,
+        time_cost: iterations,
+        lanes: parallelism,
+        thread_mode: argon2::ThreadMode::from_threads(parallelism),
+        hash_length: 32,
+        ..argon2::Config::default()
+    };
+
+    argon2::hash_raw(password, salt.as_slice(), &config)
+        .context("Could not derive key with Argon2id")
+}
+
+/// Decrypts an AES cipher string (types 0 to 2). Type 0 has no mac, and type 1 uses the two
+/// halves of a 32 byte key as the encryption and mac keys.
 pub fn decrypt_cipher_string(
         key: &models::SymmetricKey, cipher_string: &models::CipherString)
         -> Result<Vec<u8>> {
 
-    if !check_macs(&key.mac, cipher_string)? {
-        bail!("Decryption failed.");
+    match cipher_string.enc_type {
+        0 => decrypt_aes(&key.key, &cipher_string.iv, &cipher_string.data),
+        1 => {
+            if key.key.len() != 32 || !key.mac.is_empty() {
+                bail!("Cipher string of type 1 needs a 32 byte key");
+            }
+
+            let (enc_key, mac_key) = key.key.split_at(16);
+            if !check_macs(mac_key, cipher_string)? {
+                bail!("Decryption failed.");
+            };
+
+            Ok(Aes128Cbc::new_var(enc_key, &cipher_string.iv)
+                .context("Could not initialize decryption algorithm")?
+                .decrypt_vec(&cipher_string.data)
+                .context("Could not decrypt ciphertext")?)
+        },
+        2 => {
+            if key.mac.is_empty() {
+                bail!("Cipher string of type 2 needs a key with a mac key");
+            }
+
+            if !check_macs(&key.mac, cipher_string)? {
+                bail!("Decryption failed.");
+            };
+
+            decrypt_aes(&key.key, &cipher_string.iv, &cipher_string.data)
+        },
+        v => bail!("Cipher string of type {} can't be decrypted with a symmetric key", v)
+    }
+}
+
+/// Decrypts an RSA-OAEP cipher string (types 3 to 6). The mac of types 5 and 6 is not checked,
+/// same as in the Bitwarden clients.
+pub fn decrypt_rsa(key: &models::PrivateKey, cipher_string: &models::CipherString) -> Result<Vec<u8>> {
+    let padding = match cipher_string.enc_type {
+        3 | 5 => PaddingScheme::new_oaep::<Sha256>(),
+        4 | 6 => PaddingScheme::new_oaep::<Sha1>(),
+        v => bail!("Cipher string of type {} can't be decrypted with a private key", v)
     };
 
-    Ok(decrypt_aes(&key.key, &cipher_string.iv, &cipher_string.data)?)
+    key.key
+        .decrypt(padding, &cipher_string.data)
+        .context("Could not decrypt ciphertext")
 }
 
 pub fn encrypt_cipher_string(key: &models::SymmetricKey, data: &Vec<u8>) -> Result<models::CipherString> {
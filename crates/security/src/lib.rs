@@ -1,3 +1,4 @@
 pub mod models;
+pub mod totp;
 
 mod crypt;
@@ -1,8 +1,10 @@
+use std::convert::TryFrom;
+
 use base64;
 use serde::{Serialize, Deserialize};
 use rand_core::{OsRng, RngCore};
 
-use anyhow::{Context, Result};
+use anyhow::{bail, Context, Result};
 
 use crate::crypt;
 
@@ -36,38 +38,114 @@ impl From<&str> for StringWrapper {
     }
 }
 
+/// A part of the `|` separated data of a cipher string
+#[derive(Clone, Copy)]
+enum Part {
+    Iv,
+    Data,
+    Mac,
+}
+
+impl Part {
+    fn name(self) -> &'static str {
+        match self {
+            Self::Iv => "iv",
+            Self::Data => "data",
+            Self::Mac => "mac",
+        }
+    }
+}
+
+impl CipherString {
+    /**
+    Reads the binary form files (attachments) are encrypted in: one byte with the encryption
+    type, then the iv (16 bytes), the mac (32 bytes) and the data. Types 0 to 2 are supported,
+    type 0 has no mac.
+    */
+    pub fn from_buffer(buffer: &[u8]) -> Result<Self> {
+        let enc_type = *buffer.first()
+            .context("Missing encryption type on encrypted file")? as i32;
+
+        let mac_len = match enc_type {
+            0 => 0,
+            1 | 2 => 32,
+            v => bail!("Unsupported encryption type on encrypted file: {}", v)
+        };
+
+        if buffer.len() < 1 + 16 + mac_len + 1 {
+            bail!("Encrypted file is too short: {} bytes", buffer.len());
+        }
+
+        let (iv, rest) = buffer[1..].split_at(16);
+        let (mac, data) = rest.split_at(mac_len);
+
+        Ok(CipherString { enc_type, iv: iv.to_vec(), mac: mac.to_vec(), data: data.to_vec(), raw: None })
+    }
+
+    /**
+    The parts a cipher string of each encryption type consists of, in order:
+        0 - AES-CBC-256, no mac
+        1 - AES-CBC-128 with HMAC-SHA256
+        2 - AES-CBC-256 with HMAC-SHA256
+        3 - RSA-2048 OAEP with SHA256
+        4 - RSA-2048 OAEP with SHA1
+        5 - RSA-2048 OAEP with SHA256, with HMAC-SHA256
+        6 - RSA-2048 OAEP with SHA1, with HMAC-SHA256
+    */
+    fn layout(enc_type: i32) -> Option<&'static [Part]> {
+        Some(match enc_type {
+            0 => &[Part::Iv, Part::Data],
+            1 | 2 => &[Part::Iv, Part::Data, Part::Mac],
+            3 | 4 => &[Part::Data],
+            5 | 6 => &[Part::Data, Part::Mac],
+            _ => return None
+        })
+    }
+
+    fn part(&self, part: Part) -> &Vec<u8> {
+        match part {
+            Part::Iv => &self.iv,
+            Part::Data => &self.data,
+            Part::Mac => &self.mac,
+        }
+    }
+}
+
 impl From<&StringWrapper> for Result<CipherString> {
     fn from(cipher_string: &StringWrapper) -> Self {
         let cipher_string: String = cipher_string.into();
         let raw = Some(cipher_string.clone());
 
-        let mut parts = cipher_string.split('.');
-        let enc_type = parts.next().unwrap_or("2");
-        let enc_type = enc_type.parse::<i32>().unwrap_or(2);
+        let mut header = cipher_string.splitn(2, '.');
+        let enc_type = header.next().unwrap_or("");
+        let composite = header.next()
+            .context("Missing encryption type on CipherString")?;
 
-        parts = parts.next()
-            .context("Missing composite data part on CipherString")?
-            .split('|');
+        let enc_type = enc_type.parse::<i32>()
+            .context(format!("Invalid encryption type on CipherString: {}", enc_type))?;
+        let layout = CipherString::layout(enc_type)
+            .context(format!("Unknown encryption type on CipherString: {}", enc_type))?;
 
-        let iv = base64::decode(parts
-                .next()
-                .context("Missing iv part on CipherString")?
-            )
-            .context("Could not decode iv on CipherString")?;
+        let parts: Vec<&str> = composite.split('|').collect();
+        if parts.len() != layout.len() {
+            bail!("Expected {} parts for encryption type {} on CipherString, found {}",
+                layout.len(), enc_type, parts.len());
+        }
 
-        let data = base64::decode(parts
-                .next()
-                .context("Missing data part on CipherString")?
-            )
-            .context("Could not decode data on CipherString")?;
+        let mut out = CipherString { enc_type, iv: Vec::new(), data: Vec::new(), mac: Vec::new(), raw };
 
-        let mac = base64::decode(parts
-                .next()
-                .context("Missing mac part on CipherString")?
-            )
-            .context("Could not decode mac on CipherString")?;
+        for (part, value) in layout.iter().zip(parts) {
+            let value = base64::decode(value)
+                .context(format!("Could not decode {} on CipherString", part.name()))?;
+
+            match part {
+                Part::Iv => out.iv = value,
+                Part::Data => out.data = value,
+                Part::Mac => out.mac = value,
+            }
+        }
 
-        Ok(CipherString { enc_type, iv, data, mac, raw })
+        Ok(out)
     }
 }
 
@@ -76,11 +154,14 @@ impl ToString for CipherString {
         match &self.raw {
             Some(v) => v.clone(),
             None => format!(
-                "{}.{}|{}|{}",
+                "{}.{}",
                 self.enc_type,
-                base64::encode(&self.iv),
-                base64::encode(&self.data),
-                base64::encode(&self.mac))
+                CipherString::layout(self.enc_type)
+                    .unwrap_or(&[])
+                    .iter()
+                    .map(|part| base64::encode(self.part(*part)))
+                    .collect::<Vec<String>>()
+                    .join("|"))
         }
     }
 }
@@ -104,11 +185,7 @@ impl<'de> serde::de::Visitor<'de> for CipherStringVisitor {
     where E: serde::de::Error {
         let v_wrapper = StringWrapper::from(v);
         let cs: Result<CipherString> = (&v_wrapper).into();
-        // let cs = cs?;
-        match cs {
-            Ok(v) => Ok(v),
-            Err(_) => Err(serde::de::Error::invalid_value(serde::de::Unexpected::Str(v), &self))
-        }
+        cs.map_err(|e| serde::de::Error::custom(format!("{:#}", e)))
     }
 
     fn expecting(&self, formatter: &mut std::fmt::Formatter) -> std::fmt::Result {
@@ -123,11 +200,19 @@ impl<'de> Deserialize<'de> for CipherString {
     }
 }
 
+/// The key derivation function an account uses for its master key
+#[derive(Clone, Debug, PartialEq)]
+pub enum Kdf {
+    Pbkdf2 { iterations: u32 },
+    /// `memory` is in MiB
+    Argon2id { iterations: u32, memory: u32, parallelism: u32 },
+}
+
 #[derive(Clone, Debug)]
 pub struct Credentials {
     pub email: String,
     pub password: String,
-    pub iterations: u32
+    pub kdf: Kdf
 }
 
 #[derive(Clone, Debug)]
@@ -136,13 +221,21 @@ pub struct MasterKey {
     pub hash: String
 }
 
-impl From<&Credentials> for MasterKey {
-    fn from(input: &Credentials) -> Self {
+impl TryFrom<&Credentials> for MasterKey {
+    type Error = anyhow::Error;
+
+    fn try_from(input: &Credentials) -> Result<Self> {
         // derive master password using email as salt
-        let key = crypt::generate_pbkdf(
-            input.password.as_bytes(),
-            input.email.as_bytes(),
-            input.iterations);
+        let key = match input.kdf {
+            Kdf::Pbkdf2 { iterations } => crypt::generate_pbkdf(
+                input.password.as_bytes(),
+                input.email.as_bytes(),
+                iterations),
+            Kdf::Argon2id { iterations, memory, parallelism } => crypt::generate_argon2id(
+                input.password.as_bytes(),
+                input.email.as_bytes(),
+                iterations, memory, parallelism)?
+        };
 
         // run one iteration of derivation with the master password as salt
         let hash = crypt::generate_pbkdf(
@@ -152,7 +245,23 @@ impl From<&Credentials> for MasterKey {
 
         let hash = base64::encode(hash);
 
-        Self { key, hash }
+        Ok(Self { key, hash })
+    }
+}
+
+/// The RSA private key of an account, used to decrypt the keys of its organizations
+#[derive(Clone, Debug)]
+pub struct PrivateKey {
+    pub key: rsa::RSAPrivateKey
+}
+
+impl PrivateKey {
+    /// Reads a PKCS#8 DER encoded key, as stored (encrypted) in the account profile
+    pub fn from_der(der: &[u8]) -> Result<Self> {
+        let key = rsa::RSAPrivateKey::from_pkcs8(der)
+            .context("Could not read private key")?;
+
+        Ok(Self { key })
     }
 }
 
@@ -198,7 +307,13 @@ impl From<Option<String>> for SymmetricKey {
 }
 
 impl From<Vec<u8>> for SymmetricKey {
+    /// 64 byte keys are split into an encryption and a mac key. 32 byte keys are used as they
+    /// are, for cipher strings of type 0 and 1.
     fn from(input: Vec<u8>) -> Self {
+        if input.len() == 32 {
+            return Self { mac: Vec::new(), key: input };
+        }
+
         let key = input[..32].to_vec();
         let mac = input[32..64].to_vec();
 
@@ -260,3 +375,93 @@ impl Decrypt<&Vec<u8>> for CipherString {
             &key, self)
     }
 }
+
+impl Decrypt<&PrivateKey> for CipherString {
+    fn decrypt(&self, key: &PrivateKey) -> anyhow::Result<Vec<u8>> {
+        crypt::decrypt_rsa(key, self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(value: &str) -> Result<CipherString> {
+        (&StringWrapper::from(value)).into()
+    }
+
+    /// Formats the parts again instead of returning the string the cipher string was read from
+    fn format(cipher_string: &CipherString) -> String {
+        CipherString { raw: None, ..cipher_string.clone() }.to_string()
+    }
+
+    #[test]
+    fn reads_every_type() {
+        let (iv, data, mac) = (base64::encode(b"iv"), base64::encode(b"data"), base64::encode(b"mac"));
+
+        let values = [
+            format!("0.{}|{}", iv, data),
+            format!("1.{}|{}|{}", iv, data, mac),
+            format!("2.{}|{}|{}", iv, data, mac),
+            format!("3.{}", data),
+            format!("4.{}", data),
+            format!("5.{}|{}", data, mac),
+            format!("6.{}|{}", data, mac),
+        ];
+
+        for value in values.iter() {
+            let cipher_string = parse(value).unwrap();
+
+            assert_eq!(cipher_string.data, b"data", "{}", value);
+            assert_eq!(cipher_string.to_string(), *value);
+            assert_eq!(format(&cipher_string), *value);
+        }
+
+        let cipher_string = parse(&values[2]).unwrap();
+        assert_eq!(cipher_string.enc_type, 2);
+        assert_eq!(cipher_string.iv, b"iv");
+        assert_eq!(cipher_string.mac, b"mac");
+
+        let cipher_string = parse(&values[5]).unwrap();
+        assert_eq!(cipher_string.enc_type, 5);
+        assert!(cipher_string.iv.is_empty());
+        assert_eq!(cipher_string.mac, b"mac");
+    }
+
+    #[test]
+    fn rejects_invalid_strings() {
+        for value in &["9.x", "2.a|b", "4.a|b", "0.a", "abc", "x.abc", "2.!|!|!"] {
+            assert!(parse(value).is_err(), "{}", value);
+        }
+    }
+
+    #[test]
+    fn reads_buffers() {
+        let mut buffer = vec![2u8];
+        buffer.extend(&[1u8; 16]);
+        buffer.extend(&[2u8; 32]);
+        buffer.extend(b"data");
+
+        let cipher_string = CipherString::from_buffer(&buffer).unwrap();
+        assert_eq!(cipher_string.enc_type, 2);
+        assert_eq!(cipher_string.iv, vec![1u8; 16]);
+        assert_eq!(cipher_string.mac, vec![2u8; 32]);
+        assert_eq!(cipher_string.data, b"data");
+
+        let mut buffer = vec![0u8];
+        buffer.extend(&[1u8; 16]);
+        buffer.extend(b"data");
+
+        let cipher_string = CipherString::from_buffer(&buffer).unwrap();
+        assert!(cipher_string.mac.is_empty());
+        assert_eq!(cipher_string.data, b"data");
+    }
+
+    #[test]
+    fn rejects_invalid_buffers() {
+        assert!(CipherString::from_buffer(&[]).is_err());
+        assert!(CipherString::from_buffer(&[3u8; 64]).is_err());
+        // a type 2 buffer without any data after the mac
+        assert!(CipherString::from_buffer(&[2u8; 49]).is_err());
+    }
+}
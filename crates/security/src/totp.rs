@@ -0,0 +1,243 @@
+use hmac::{Hmac, Mac, NewMac};
+use sha1::Sha1;
+use sha2::{Sha256, Sha512};
+use anyhow::{Result, anyhow, bail, Context};
+
+
+const STEAM_ALPHABET: &[u8] = b"23456789BCDFGHJKMNPQRTVWXY";
+const STEAM_DIGITS: u32 = 5;
+
+
+/// The hash function the HMAC of a TOTP code is computed with
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Algorithm {
+    Sha1,
+    Sha256,
+    Sha512,
+}
+
+/// A TOTP generator (RFC 6238), as stored in the `totp` field of a Bitwarden login
+#[derive(Clone, Debug)]
+pub struct Totp {
+    secret: Vec<u8>,
+    algorithm: Algorithm,
+    digits: u32,
+    /// seconds a code is valid for
+    period: u64,
+    /// whether the code is made of Steam Guard characters instead of decimal digits
+    steam: bool,
+}
+
+impl Totp {
+    /**
+    Parses the value of a `totp` field, which can be:
+        an `otpauth://totp/...` URI - the `secret` is required, `algorithm`, `digits` and `period`
+            default to SHA1, 6 and 30. `encoder=steam` makes it a Steam code.
+        a `steam://<secret>` URI - a Steam Guard code
+        a bare base32 secret - the defaults above
+    */
+    pub fn parse(value: &str) -> Result<Self> {
+        let value = value.trim();
+        let lower = value.to_lowercase();
+
+        if lower.starts_with("otpauth://") {
+            return Self::from_uri(value);
+        }
+
+        if lower.starts_with("steam://") {
+            return Ok(Self { steam: true, digits: STEAM_DIGITS, ..Self::new(decode_secret(&value[8..])?) });
+        }
+
+        Ok(Self::new(decode_secret(value)?))
+    }
+
+    fn new(secret: Vec<u8>) -> Self {
+        Self { secret, algorithm: Algorithm::Sha1, digits: 6, period: 30, steam: false }
+    }
+
+    fn from_uri(value: &str) -> Result<Self> {
+        let uri = url::Url::parse(value).context("Invalid otpauth URI")?;
+
+        if !uri.host_str().map_or(false, |h| h.eq_ignore_ascii_case("totp")) {
+            bail!("Only TOTP otpauth URIs are supported");
+        }
+
+        let mut secret = None;
+        let mut out = Self::new(Vec::new());
+
+        for (key, v) in uri.query_pairs() {
+            match key.to_lowercase().as_str() {
+                "secret" => secret = Some(decode_secret(&v)?),
+                "algorithm" => out.algorithm = match v.to_uppercase().as_str() {
+                    "SHA1" => Algorithm::Sha1,
+                    "SHA256" => Algorithm::Sha256,
+                    "SHA512" => Algorithm::Sha512,
+                    v => bail!("Unknown TOTP algorithm: {}", v)
+                },
+                "digits" => out.digits = match v.parse::<u32>() {
+                    Ok(v) if (1..=10).contains(&v) => v,
+                    _ => bail!("Invalid number of TOTP digits: {}", v)
+                },
+                "period" => out.period = match v.parse::<u64>() {
+                    Ok(v) if v > 0 => v,
+                    _ => bail!("Invalid TOTP period: {}", v)
+                },
+                "encoder" if v.eq_ignore_ascii_case("steam") => {
+                    out.steam = true;
+                    out.digits = STEAM_DIGITS;
+                },
+                _ => ()
+            }
+        }
+
+        out.secret = secret.ok_or_else(|| anyhow!("Missing the secret of the otpauth URI"))?;
+
+        Ok(out)
+    }
+
+    /// Seconds the code for the given unix time is still valid for
+    pub fn remaining(&self, time: u64) -> u64 {
+        self.period - time % self.period
+    }
+
+    /// The code for the given unix time
+    pub fn code(&self, time: u64) -> Result<String> {
+        let counter = (time / self.period).to_be_bytes();
+
+        let hash = match self.algorithm {
+            Algorithm::Sha1 => hmac::<Hmac<Sha1>>(&self.secret, &counter)?,
+            Algorithm::Sha256 => hmac::<Hmac<Sha256>>(&self.secret, &counter)?,
+            Algorithm::Sha512 => hmac::<Hmac<Sha512>>(&self.secret, &counter)?,
+        };
+
+        // dynamic truncation, RFC 4226 section 5.3
+        let offset = (hash[hash.len() - 1] & 0xf) as usize;
+        let mut binary = u32::from_be_bytes([hash[offset], hash[offset + 1], hash[offset + 2], hash[offset + 3]])
+            & 0x7fff_ffff;
+
+        if self.steam {
+            let mut out = String::new();
+            for _ in 0..self.digits {
+                out.push(STEAM_ALPHABET[binary as usize % STEAM_ALPHABET.len()] as char);
+                binary /= STEAM_ALPHABET.len() as u32;
+            }
+            return Ok(out);
+        }
+
+        Ok(format!("{:0width$}", binary as u64 % 10u64.pow(self.digits), width = self.digits as usize))
+    }
+}
+
+fn hmac<M: Mac + NewMac>(key: &[u8], data: &[u8]) -> Result<Vec<u8>> {
+    let mut mac = M::new_varkey(key)
+        .or_else(|_| bail!("Could not create hmac key"))?;
+    mac.update(data);
+
+    Ok(mac.finalize().into_bytes().to_vec())
+}
+
+/// Decodes a base32 secret, ignoring case, spaces and padding
+fn decode_secret(value: &str) -> Result<Vec<u8>> {
+    let cleaned: String = value
+        .chars()
+        .filter(|c| !c.is_whitespace() && *c != '=' && *c != '-')
+        .collect::<String>()
+        .to_uppercase();
+
+    match base32::decode(base32::Alphabet::RFC4648 { padding: false }, &cleaned) {
+        Some(v) if !v.is_empty() => Ok(v),
+        _ => bail!("Invalid base32 TOTP secret")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SHA1_SECRET: &str = "GEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQ";
+    const SHA256_SECRET: &str = "GEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQGEZA";
+    const SHA512_SECRET: &str = "GEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQ\
+        GEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQGEZDGNA";
+
+    fn uri(secret: &str, algorithm: &str) -> String {
+        format!("otpauth://totp/test?secret={}&algorithm={}&digits=8", secret, algorithm)
+    }
+
+    /// The test vectors of RFC 6238, appendix B
+    #[test]
+    fn matches_rfc_6238() {
+        let vectors = [
+            (SHA1_SECRET, "SHA1", 59, "94287082"),
+            (SHA256_SECRET, "SHA256", 59, "46119246"),
+            (SHA512_SECRET, "SHA512", 59, "90693936"),
+            (SHA1_SECRET, "SHA1", 1111111109, "07081804"),
+            (SHA256_SECRET, "SHA256", 1111111109, "68084774"),
+            (SHA512_SECRET, "SHA512", 1111111109, "25091201"),
+            (SHA1_SECRET, "SHA1", 1234567890, "89005924"),
+            (SHA256_SECRET, "SHA256", 2000000000, "90698825"),
+            (SHA512_SECRET, "SHA512", 20000000000, "47863826"),
+        ];
+
+        for (secret, algorithm, time, code) in vectors.iter() {
+            let totp = Totp::parse(&uri(secret, algorithm)).unwrap();
+            assert_eq!(totp.code(*time).unwrap(), *code, "{} at {}", algorithm, time);
+        }
+    }
+
+    #[test]
+    fn reads_bare_secrets() {
+        let totp = Totp::parse(" gezd gnbv gy3t qojq gezd gnbv gy3t qojq== ").unwrap();
+
+        assert_eq!(totp.algorithm, Algorithm::Sha1);
+        assert_eq!(totp.code(59).unwrap(), "287082");
+        assert_eq!(totp.code(1111111109).unwrap(), "081804");
+    }
+
+    #[test]
+    fn reads_steam_secrets() {
+        let values = [
+            format!("steam://{}", SHA1_SECRET),
+            format!("otpauth://totp/x?secret={}&encoder=steam", SHA1_SECRET),
+        ];
+
+        for value in values.iter() {
+            let totp = Totp::parse(value).unwrap();
+            assert_eq!(totp.code(59).unwrap(), "PV9M4", "{}", value);
+            assert_eq!(totp.code(1111111109).unwrap(), "PY4YB", "{}", value);
+        }
+    }
+
+    #[test]
+    fn reads_uri_parameters() {
+        let totp = Totp::parse(&format!("otpauth://totp/x?secret={}&period=60&digits=7", SHA1_SECRET)).unwrap();
+
+        assert_eq!(totp.period, 60);
+        assert_eq!(totp.code(59).unwrap().len(), 7);
+    }
+
+    #[test]
+    fn rejects_invalid_seeds() {
+        let invalid = [
+            "",
+            "not base32!",
+            "otpauth://hotp/x?secret=GEZDGNBV",
+            "otpauth://totp/x",
+            "otpauth://totp/x?secret=GEZDGNBV&algorithm=MD5",
+            "otpauth://totp/x?secret=GEZDGNBV&digits=0",
+            "otpauth://totp/x?secret=GEZDGNBV&period=0",
+        ];
+
+        for value in invalid.iter() {
+            assert!(Totp::parse(value).is_err(), "{}", value);
+        }
+    }
+
+    #[test]
+    fn counts_remaining_seconds() {
+        let totp = Totp::parse(SHA1_SECRET).unwrap();
+
+        assert_eq!(totp.remaining(59), 1);
+        assert_eq!(totp.remaining(60), 30);
+        assert_eq!(totp.remaining(75), 15);
+    }
+}
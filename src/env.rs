@@ -1,17 +1,73 @@
-use std::{collections::HashMap, path::Path};
+use std::path::Path;
 
 use anyhow::{Context, Result, anyhow};
 
-pub fn get_env_vars(file_path: &str) -> Result<HashMap<String, String>> {
+/// A variable parsed from dotenv content, such as an .env file
+pub struct FileVar {
+    pub key: String,
+    pub value: String,
+    /// the line the variable was defined on, if it could be determined
+    pub line: Option<usize>,
+}
+
+/// Finds the last line that defines `key`. Later definitions overwrite earlier ones.
+fn find_line(contents: &str, key: &str) -> Option<usize> {
+    contents
+        .lines()
+        .enumerate()
+        .filter(|(_, line)| {
+            let line = line.trim_start();
+            let line = line.strip_prefix("export ").map(|l| l.trim_start()).unwrap_or(line);
+
+            match line.strip_prefix(key) {
+                Some(rest) => rest.trim_start().starts_with('='),
+                None => false
+            }
+        })
+        .map(|(i, _)| i + 1)
+        .last()
+}
+
+pub fn get_env_vars(file_path: &str) -> Result<Vec<FileVar>> {
     let contents = std::fs::read_to_string(Path::new(file_path))
         .context(format!("Could not read file: {}", file_path))?;
 
-    let envs = dotenv_parser::parse_dotenv(&contents)
-        .map_err(|e| anyhow!(e))
-        .context(format!("Could not parse file: {}", file_path))?;
+    parse(&contents)
+        .context(format!("Could not parse file: {}", file_path))
+}
+
+/// Parses dotenv content, returning the variables in the order they are defined
+pub fn parse(contents: &str) -> Result<Vec<FileVar>> {
+    let envs = dotenv_parser::parse_dotenv(contents)
+        .map_err(|e| anyhow!(e))?;
+
+    let mut out: Vec<FileVar> = envs
+        .iter()
+        .map(|(k, v)| FileVar {
+            key: k.to_string(),
+            value: v.to_string(),
+            line: find_line(contents, k)
+        })
+        .collect();
 
-    let mut out: HashMap<String, String> = HashMap::new();
-    envs.iter().for_each(|(k, v)| {out.insert(k.to_string(), v.to_string());});
+    out.sort_by_key(|v| v.line);
 
     Ok(out)
 }
+
+/// The line dotenv content stops being valid at: the one after the longest valid run of lines
+/// from the start. None if the content is valid.
+pub fn error_line(contents: &str) -> Option<usize> {
+    let lines: Vec<&str> = contents.lines().collect();
+
+    if dotenv_parser::parse_dotenv(contents).is_ok() {
+        return None;
+    }
+
+    // a value spanning several lines makes the lines before its end invalid on their own, so the
+    // longest valid prefix is searched for, not the shortest invalid one
+    (0..lines.len())
+        .rev()
+        .find(|&n| dotenv_parser::parse_dotenv(&lines[..n].join("\n")).is_ok())
+        .map(|n| n + 1)
+}
@@ -0,0 +1,276 @@
+use std::{collections::BTreeMap, str::FromStr};
+
+use anyhow::{anyhow, bail, Error, Result};
+
+
+/// Output formats available when listing variables
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Format {
+    /// `export KEY='value'` lines for sh, bash, zsh and the like
+    Posix,
+    /// `set -gx KEY 'value'` lines
+    Fish,
+    /// a `load-env` record
+    Nushell,
+    /// `$env:KEY = 'value'` lines
+    Powershell,
+    Json,
+    Yaml,
+    /// `KEY=value` lines as read by `docker run --env-file`. Values are taken literally.
+    Docker,
+    /// `KEY="value"` lines as read by systemd's `EnvironmentFile=`
+    Systemd,
+    /// lines to be appended to `$GITHUB_ENV`, using the heredoc syntax for multiline values
+    GithubActions,
+}
+
+pub const FORMAT_NAMES: [&str; 9] = [
+    "posix", "fish", "nushell", "powershell", "json", "yaml", "docker", "systemd", "github-actions"];
+
+impl FromStr for Format {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(match s {
+            "posix" => Self::Posix,
+            "fish" => Self::Fish,
+            "nushell" => Self::Nushell,
+            "powershell" => Self::Powershell,
+            "json" => Self::Json,
+            "yaml" => Self::Yaml,
+            "docker" => Self::Docker,
+            "systemd" => Self::Systemd,
+            "github-actions" => Self::GithubActions,
+            v => return Err(anyhow!("Unknown format: {} (expected one of: {})", v, FORMAT_NAMES.join(", ")))
+        })
+    }
+}
+
+/// Quotes a string for JSON and for the formats that accept JSON string escapes
+fn json_quote(value: &str) -> String {
+    // serializing a str can't fail
+    serde_json::to_string(value).unwrap_or_default()
+}
+
+fn posix_quote(value: &str) -> String {
+    format!("'{}'", value.replace('\'', "'\\''"))
+}
+
+fn fish_quote(value: &str) -> String {
+    format!("'{}'", value.replace('\\', "\\\\").replace('\'', "\\'"))
+}
+
+fn powershell_quote(value: &str) -> String {
+    format!("'{}'", value.replace('\'', "''"))
+}
+
+fn systemd_quote(value: &str) -> String {
+    let mut out = String::from("\"");
+    for c in value.chars() {
+        if "\"\\`$".contains(c) {
+            out.push('\\');
+        }
+        out.push(c);
+    }
+    out.push('"');
+
+    out
+}
+
+/// Whether a variable name can be written unquoted: `[A-Za-z_][A-Za-z0-9_]*`
+fn is_valid_name(key: &str) -> bool {
+    let mut chars = key.chars();
+
+    match chars.next() {
+        Some(c) if c.is_ascii_alphabetic() || c == '_' => chars.all(|c| c.is_ascii_alphanumeric() || c == '_'),
+        _ => false
+    }
+}
+
+/// Picks a heredoc delimiter that does not occur as a line of the value
+fn heredoc_delimiter(value: &str) -> String {
+    let mut delimiter = "NXCMDR_EOF".to_string();
+    let mut counter = 0;
+
+    while value.lines().any(|l| l == delimiter) {
+        counter += 1;
+        delimiter = format!("NXCMDR_EOF_{}", counter);
+    }
+
+    delimiter
+}
+
+/// Renders the variables in the given format, sorted by name
+pub fn render(format: Format, envs: &BTreeMap<String, String>) -> Result<String> {
+    if format == Format::Json {
+        return Ok(format!("{}\n", serde_json::to_string_pretty(envs)?));
+    }
+
+    if format == Format::Yaml && envs.is_empty() {
+        return Ok("{}\n".to_string());
+    }
+
+    let mut out = String::new();
+
+    if format == Format::Nushell {
+        out.push_str("load-env {\n");
+    }
+
+    for (key, value) in envs {
+        // names are written as they are in every format but these, so anything else could be run
+        // as code by the shell loading them
+        if ![Format::Nushell, Format::Yaml].contains(&format) && !is_valid_name(key) {
+            bail!("{:?} is not a valid variable name, which can only be listed as json, yaml or nushell", key);
+        }
+
+        let line = match format {
+            Format::Posix => format!("export {}={}", key, posix_quote(value)),
+            Format::Fish => format!("set -gx {} {}", key, fish_quote(value)),
+            Format::Nushell => format!("    {}: {}", json_quote(key), json_quote(value)),
+            Format::Powershell => format!("$env:{} = {}", key, powershell_quote(value)),
+            Format::Yaml => format!("{}: {}", json_quote(key), json_quote(value)),
+            Format::Docker => {
+                if value.contains('\n') {
+                    bail!("{} has a multiline value, which can't be written in the docker format", key);
+                }
+                format!("{}={}", key, value)
+            },
+            Format::Systemd => format!("{}={}", key, systemd_quote(value)),
+            Format::GithubActions => {
+                if value.contains('\n') || value.contains('\r') {
+                    let delimiter = heredoc_delimiter(value);
+                    format!("{}<<{}\n{}\n{}", key, delimiter, value, delimiter)
+                } else {
+                    format!("{}={}", key, value)
+                }
+            },
+            Format::Json => unreachable!(),
+        };
+
+        out.push_str(&line);
+        out.push('\n');
+    }
+
+    if format == Format::Nushell {
+        out.push_str("}\n");
+    }
+
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn envs(pairs: &[(&str, &str)]) -> BTreeMap<String, String> {
+        pairs.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect()
+    }
+
+    #[test]
+    fn quotes_posix() {
+        assert_eq!(posix_quote("plain"), "'plain'");
+        assert_eq!(posix_quote("it's"), "'it'\\''s'");
+        assert_eq!(posix_quote("$HOME `id` \\n"), "'$HOME `id` \\n'");
+        assert_eq!(posix_quote("a\nb"), "'a\nb'");
+    }
+
+    #[test]
+    fn posix_output_is_read_back_by_sh() {
+        let value = "it's \"$HOME\" `id` \\ $(id)\nsecond line\n";
+        let out = render(Format::Posix, &envs(&[("VALUE", value)])).unwrap();
+
+        let res = std::process::Command::new("sh")
+            .arg("-c")
+            .arg(format!("{}printf %s \"$VALUE\"", out))
+            .output()
+            .unwrap();
+
+        assert_eq!(String::from_utf8(res.stdout).unwrap(), value);
+    }
+
+    #[test]
+    fn quotes_fish() {
+        assert_eq!(fish_quote("it's"), "'it\\'s'");
+        assert_eq!(fish_quote("a\\'b"), "'a\\\\\\'b'");
+        assert_eq!(fish_quote("$HOME"), "'$HOME'");
+    }
+
+    #[test]
+    fn quotes_powershell() {
+        assert_eq!(powershell_quote("it's"), "'it''s'");
+        assert_eq!(powershell_quote("$env:HOME"), "'$env:HOME'");
+    }
+
+    #[test]
+    fn quotes_systemd() {
+        assert_eq!(systemd_quote("a \"b\" \\c"), "\"a \\\"b\\\" \\\\c\"");
+        assert_eq!(systemd_quote("$HOME `id`"), "\"\\$HOME \\`id\\`\"");
+        assert_eq!(systemd_quote("a\nb"), "\"a\nb\"");
+    }
+
+    #[test]
+    fn quotes_json() {
+        assert_eq!(json_quote("a \"b\"\n$c"), "\"a \\\"b\\\"\\n$c\"");
+    }
+
+    #[test]
+    fn validates_names() {
+        for name in &["A", "_", "a_1", "_PRIVATE", "Path"] {
+            assert!(is_valid_name(name), "{}", name);
+        }
+        for name in &["", "1A", "A-B", "A B", "A=B", "A;id", "$(id)", "Ä"] {
+            assert!(!is_valid_name(name), "{}", name);
+        }
+    }
+
+    #[test]
+    fn rejects_invalid_names() {
+        let invalid = envs(&[("A;id", "x")]);
+
+        for format in &[Format::Posix, Format::Fish, Format::Powershell, Format::Docker, Format::Systemd, Format::GithubActions] {
+            assert!(render(*format, &invalid).is_err(), "{:?}", format);
+        }
+        for format in &[Format::Json, Format::Yaml, Format::Nushell] {
+            assert!(render(*format, &invalid).is_ok(), "{:?}", format);
+        }
+    }
+
+    #[test]
+    fn picks_a_heredoc_delimiter_not_in_the_value() {
+        assert_eq!(heredoc_delimiter("a\nb"), "NXCMDR_EOF");
+        assert_eq!(heredoc_delimiter("a\nNXCMDR_EOF\nb"), "NXCMDR_EOF_1");
+        assert_eq!(heredoc_delimiter("NXCMDR_EOF\nNXCMDR_EOF_1"), "NXCMDR_EOF_2");
+        // only whole lines end the heredoc
+        assert_eq!(heredoc_delimiter("xNXCMDR_EOF\nNXCMDR_EOF "), "NXCMDR_EOF");
+    }
+
+    #[test]
+    fn renders_github_actions() {
+        let out = render(Format::GithubActions, &envs(&[("A", "one"), ("B", "x\nNXCMDR_EOF\ny")])).unwrap();
+        assert_eq!(out, "A=one\nB<<NXCMDR_EOF_1\nx\nNXCMDR_EOF\ny\nNXCMDR_EOF_1\n");
+    }
+
+    #[test]
+    fn renders_docker() {
+        assert_eq!(render(Format::Docker, &envs(&[("A", "'$B' \"c\"")])).unwrap(), "A='$B' \"c\"\n");
+        assert!(render(Format::Docker, &envs(&[("A", "a\nb")])).is_err());
+    }
+
+    #[test]
+    fn renders_structured_formats() {
+        let vars = envs(&[("A B", "x\"\ny")]);
+
+        assert_eq!(render(Format::Json, &vars).unwrap(), "{\n  \"A B\": \"x\\\"\\ny\"\n}\n");
+        assert_eq!(render(Format::Yaml, &vars).unwrap(), "\"A B\": \"x\\\"\\ny\"\n");
+        assert_eq!(render(Format::Nushell, &vars).unwrap(), "load-env {\n    \"A B\": \"x\\\"\\ny\"\n}\n");
+        assert_eq!(render(Format::Yaml, &BTreeMap::new()).unwrap(), "{}\n");
+    }
+
+    #[test]
+    fn parses_format_names() {
+        for name in FORMAT_NAMES.iter() {
+            assert!(name.parse::<Format>().is_ok(), "{}", name);
+        }
+        assert!("csv".parse::<Format>().is_err());
+    }
+}
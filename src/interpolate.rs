@@ -0,0 +1,347 @@
+use std::collections::{BTreeMap, BTreeSet};
+
+use anyhow::{anyhow, bail, Context, Result};
+use glob::Pattern;
+
+use crate::layers::Environment;
+
+
+/// A `${...}` reference found in a value
+enum Reference<'a> {
+    /// `${VAR}`
+    Plain(&'a str),
+    /// `${VAR:-default}`, the default is expanded as well
+    Default(&'a str, &'a str),
+    /// `${VAR:?message}`
+    Required(&'a str, &'a str),
+}
+
+impl<'a> Reference<'a> {
+    fn parse(inner: &'a str) -> Self {
+        if let Some(i) = inner.find(":-") {
+            Self::Default(&inner[..i], &inner[i + 2..])
+        } else if let Some(i) = inner.find(":?") {
+            Self::Required(&inner[..i], &inner[i + 2..])
+        } else {
+            Self::Plain(inner)
+        }
+    }
+}
+
+/// Expands `${...}` references in the values of the merged environment.
+struct Interpolator<'a> {
+    raw: &'a BTreeMap<String, String>,
+    /// the variables nxc inherited and passes on, for references to variables that weren't loaded
+    inherited: &'a BTreeMap<String, String>,
+    literal: &'a [Pattern],
+    /// variables that are literal whatever their name, such as passwords mapped from logins
+    literal_vars: BTreeSet<String>,
+    resolved: BTreeMap<String, String>,
+    /// variables currently being expanded, used to detect cycles
+    stack: Vec<String>,
+}
+
+impl<'a> Interpolator<'a> {
+    fn is_literal(&self, key: &str) -> bool {
+        self.literal_vars.contains(key) || self.literal.iter().any(|p| p.matches(key))
+    }
+
+    fn resolve(&mut self, key: &str) -> Result<Option<String>> {
+        if let Some(v) = self.resolved.get(key) {
+            return Ok(Some(v.clone()));
+        }
+
+        // inherited values are taken as they are
+        let raw = match self.raw.get(key) {
+            Some(v) => v,
+            None => return Ok(self.inherited.get(key).cloned())
+        };
+
+        if self.is_literal(key) {
+            return Ok(Some(raw.clone()));
+        }
+
+        if self.stack.iter().any(|k| k == key) {
+            let mut cycle = self.stack.clone();
+            cycle.push(key.to_string());
+            bail!("Circular reference: {}", cycle.join(" -> "));
+        }
+
+        self.stack.push(key.to_string());
+        let value = self.expand(raw);
+        self.stack.pop();
+
+        let value = value.context(format!("Could not expand {}", key))?;
+        self.resolved.insert(key.to_string(), value.clone());
+
+        Ok(Some(value))
+    }
+
+    fn expand(&mut self, value: &str) -> Result<String> {
+        let mut out = String::new();
+        let mut rest = value;
+
+        while let Some(i) = rest.find('$') {
+            out.push_str(&rest[..i]);
+            rest = &rest[i..];
+
+            if rest.starts_with("$$") {
+                out.push('$');
+                rest = &rest[2..];
+            } else if rest.starts_with("${") {
+                let end = find_closing(&rest[2..])
+                    .ok_or_else(|| anyhow!("Unterminated reference: {}", rest))?;
+                let inner = &rest[2..2 + end];
+                rest = &rest[2 + end + 1..];
+
+                out.push_str(&self.reference(Reference::parse(inner))?);
+            } else {
+                // a lone `$` is kept as it is
+                out.push('$');
+                rest = &rest[1..];
+            }
+        }
+        out.push_str(rest);
+
+        Ok(out)
+    }
+
+    fn reference(&mut self, reference: Reference) -> Result<String> {
+        match reference {
+            Reference::Plain(name) => self.resolve(name)?
+                .ok_or_else(|| anyhow!("Undefined variable: {}", name)),
+            Reference::Default(name, default) => match self.resolve(name)? {
+                Some(v) if !v.is_empty() => Ok(v),
+                _ => self.expand(default)
+            },
+            Reference::Required(name, message) => match self.resolve(name)? {
+                Some(v) if !v.is_empty() => Ok(v),
+                _ => bail!("{}: {}", name, match message {
+                    "" => "parameter null or not set",
+                    v => v
+                })
+            },
+        }
+    }
+}
+
+/// Finds the `}` closing a reference, skipping over nested references in defaults
+fn find_closing(value: &str) -> Option<usize> {
+    let mut depth = 0;
+
+    for (i, c) in value.char_indices() {
+        match c {
+            '{' => depth += 1,
+            '}' if depth == 0 => return Some(i),
+            '}' => depth -= 1,
+            _ => ()
+        }
+    }
+
+    None
+}
+
+/// Expands `${VAR}`, `${VAR:-default}` and `${VAR:?error}` references in every value of the
+/// merged environment, using the merged values and then the `inherited` ones. `$$` is replaced
+/// with a single `$`. Values of variables matching any of the `literal` patterns are left
+/// untouched, as are the ones of variables marked as literal.
+pub fn interpolate(env: &mut Environment, literal: &[Pattern], inherited: &BTreeMap<String, String>) -> Result<()> {
+    let raw: BTreeMap<String, String> = env.iter().map(|(k, e)| (k.clone(), e.value.clone())).collect();
+
+    let mut interpolator = Interpolator {
+        raw: &raw,
+        inherited,
+        literal,
+        literal_vars: env.iter().filter(|(_, e)| e.literal).map(|(k, _)| k.clone()).collect(),
+        resolved: BTreeMap::new(),
+        stack: Vec::new(),
+    };
+    let mut errors: Vec<String> = Vec::new();
+
+    for (key, entry) in env.iter_mut() {
+        match interpolator.resolve(key) {
+            Ok(Some(v)) => {
+                // an expanded value may contain the value of a sensitive variable
+                if v != entry.value {
+                    entry.sensitive = true;
+                }
+                entry.value = v
+            },
+            Ok(None) => (),
+            Err(e) => errors.push(format!("{:#}", e))
+        }
+    }
+
+    if !errors.is_empty() {
+        bail!("Could not interpolate variables:\n{}", errors.join("\n"));
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::layers::{Entry, Origin};
+
+    fn environment(vars: &[(&str, &str)]) -> Environment {
+        vars.iter()
+            .map(|(k, v)| (k.to_string(), Entry {
+                value: v.to_string(),
+                origin: Origin::File { path: ".env".to_string(), line: None },
+                sensitive: false,
+                literal: false,
+                shadowed: Vec::new(),
+            }))
+            .collect()
+    }
+
+    fn map(vars: &[(&str, &str)]) -> BTreeMap<String, String> {
+        vars.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect()
+    }
+
+    fn values(env: &Environment) -> BTreeMap<String, String> {
+        env.iter().map(|(k, e)| (k.clone(), e.value.clone())).collect()
+    }
+
+    fn expand(vars: &[(&str, &str)]) -> Result<BTreeMap<String, String>> {
+        let mut env = environment(vars);
+        interpolate(&mut env, &[], &BTreeMap::new())?;
+        Ok(values(&env))
+    }
+
+    fn error(vars: &[(&str, &str)]) -> String {
+        format!("{:#}", expand(vars).unwrap_err())
+    }
+
+    #[test]
+    fn expands_references() {
+        let out = expand(&[("HOST", "db"), ("URL", "postgres://${HOST}:${PORT}/x"), ("PORT", "5432")]).unwrap();
+        assert_eq!(out["URL"], "postgres://db:5432/x");
+    }
+
+    #[test]
+    fn expands_chained_references() {
+        let out = expand(&[("A", "${B}!"), ("B", "${C}${C}"), ("C", "c")]).unwrap();
+        assert_eq!(out["A"], "cc!");
+    }
+
+    #[test]
+    fn escapes_dollars() {
+        let out = expand(&[("A", "$${B} $$$$ $B $ end$"), ("B", "b")]).unwrap();
+        assert_eq!(out["A"], "${B} $$ $B $ end$");
+    }
+
+    #[test]
+    fn expands_defaults() {
+        let out = expand(&[
+            ("EMPTY", ""),
+            ("SET", "set"),
+            ("A", "${MISSING:-default}"),
+            ("B", "${EMPTY:-default}"),
+            ("C", "${SET:-default}"),
+            ("D", "${MISSING:-}"),
+        ]).unwrap();
+
+        assert_eq!(out["A"], "default");
+        assert_eq!(out["B"], "default");
+        assert_eq!(out["C"], "set");
+        assert_eq!(out["D"], "");
+    }
+
+    #[test]
+    fn expands_nested_defaults() {
+        let out = expand(&[
+            ("SET", "set"),
+            ("A", "${MISSING:-${OTHER:-${SET}}}"),
+            ("B", "${MISSING:-${OTHER:-x}-y}z"),
+            ("C", "${MISSING:-${SET:-x}}"),
+        ]).unwrap();
+
+        assert_eq!(out["A"], "set");
+        assert_eq!(out["B"], "x-yz");
+        assert_eq!(out["C"], "set");
+    }
+
+    #[test]
+    fn only_expands_the_default_in_use() {
+        let out = expand(&[("SET", "set"), ("A", "${SET:-${MISSING}}")]).unwrap();
+        assert_eq!(out["A"], "set");
+    }
+
+    #[test]
+    fn reports_required_variables() {
+        assert!(error(&[("A", "${MISSING:?set it in .env.local}")])
+            .contains("Could not expand A: MISSING: set it in .env.local"));
+        assert!(error(&[("EMPTY", ""), ("A", "${EMPTY:?}")])
+            .contains("EMPTY: parameter null or not set"));
+        assert_eq!(expand(&[("SET", "set"), ("A", "${SET:?unused}")]).unwrap()["A"], "set");
+    }
+
+    #[test]
+    fn reports_undefined_variables() {
+        let e = error(&[("A", "${MISSING}")]);
+        assert!(e.starts_with("Could not interpolate variables:\n"), "{}", e);
+        assert!(e.contains("Could not expand A: Undefined variable: MISSING"), "{}", e);
+    }
+
+    #[test]
+    fn reports_cycles() {
+        let e = error(&[("A", "${B}"), ("B", "x${C}"), ("C", "${A}")]);
+        assert!(e.contains("Circular reference: A -> B -> C -> A"), "{}", e);
+
+        let e = error(&[("A", "${A:-x}")]);
+        assert!(e.contains("Circular reference: A -> A"), "{}", e);
+    }
+
+    #[test]
+    fn reports_unterminated_references() {
+        assert!(error(&[("A", "${B")]).contains("Unterminated reference: ${B"));
+        assert!(error(&[("A", "${B:-${C}")]).contains("Unterminated reference"));
+    }
+
+    #[test]
+    fn reports_every_error() {
+        let e = error(&[("A", "${X}"), ("B", "ok"), ("C", "${Y}")]);
+        assert!(e.contains("Undefined variable: X") && e.contains("Undefined variable: Y"), "{}", e);
+    }
+
+    #[test]
+    fn falls_back_to_inherited_variables() {
+        let mut env = environment(&[("A", "${HOME}/bin"), ("B", "${USER:-nobody}"), ("HOME", "/loaded")]);
+        let inherited = map(&[("HOME", "/home/me"), ("USER", "me"), ("RAW", "${HOME}")]);
+        interpolate(&mut env, &[], &inherited).unwrap();
+
+        // loaded variables win over inherited ones
+        assert_eq!(env["A"].value, "/loaded/bin");
+        assert_eq!(env["B"].value, "me");
+
+        // inherited values are not expanded
+        let mut env = environment(&[("A", "${RAW}")]);
+        interpolate(&mut env, &[], &inherited).unwrap();
+        assert_eq!(env["A"].value, "${HOME}");
+    }
+
+    #[test]
+    fn keeps_literal_values() {
+        let mut env = environment(&[("PASSWORD", "pa$${x}"), ("TOKEN", "${A}"), ("A", "a"), ("B", "${PASSWORD}${TOKEN}")]);
+        env.get_mut("PASSWORD").unwrap().literal = true;
+        let literal = [Pattern::new("TOK*").unwrap()];
+        interpolate(&mut env, &literal, &BTreeMap::new()).unwrap();
+
+        assert_eq!(env["PASSWORD"].value, "pa$${x}");
+        assert_eq!(env["TOKEN"].value, "${A}");
+        // literal values are not expanded when referenced either
+        assert_eq!(env["B"].value, "pa$${x}${A}");
+    }
+
+    #[test]
+    fn marks_expanded_values_sensitive() {
+        let mut env = environment(&[("A", "${B}"), ("B", "b"), ("C", "$$")]);
+        interpolate(&mut env, &[], &BTreeMap::new()).unwrap();
+
+        assert!(env["A"].sensitive);
+        assert!(!env["B"].sensitive);
+        assert!(env["C"].sensitive);
+    }
+}
@@ -0,0 +1,287 @@
+use std::{collections::BTreeMap, fmt, str::FromStr};
+
+use anyhow::{anyhow, Error};
+
+
+/// Where a set of environment variables was loaded from
+#[derive(Clone, Debug)]
+pub enum Source {
+    /// path of an .env file
+    File(String),
+    /// the `bitwarden-name` used to select the secure notes
+    Vault(String),
+    /// a Bitwarden folder the secure notes were loaded from
+    Folder(String),
+    /// an organization collection the secure notes were loaded from
+    Collection(String),
+}
+
+impl fmt::Display for Source {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::File(path) => write!(f, "file {}", path),
+            Self::Vault(name) => write!(f, "Bitwarden notes matching \"{}\"", name),
+            Self::Folder(name) => write!(f, "Bitwarden notes in folder \"{}\"", name),
+            Self::Collection(name) => write!(f, "Bitwarden notes in collection \"{}\"", name),
+        }
+    }
+}
+
+/// Where a single variable was defined
+#[derive(Clone, Debug, PartialEq)]
+pub enum Origin {
+    File { path: String, line: Option<usize> },
+    /// `line` is set for variables defined in the body of the note
+    Note { name: String, id: String, line: Option<usize> },
+    /// `line` is set for variables defined in an attachment loaded as dotenv content
+    Attachment { file_name: String, name: String, id: String, line: Option<usize> },
+}
+
+impl fmt::Display for Origin {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::File { path, line: Some(line) } => write!(f, "file {}:{}", path, line),
+            Self::File { path, line: None } => write!(f, "file {}", path),
+            Self::Note { name, id, line: Some(line) } => write!(f, "note \"{}\" ({}), line {}", name, id, line),
+            Self::Note { name, id, line: None } => write!(f, "note \"{}\" ({})", name, id),
+            Self::Attachment { file_name, name, id, line: Some(line) } =>
+                write!(f, "attachment \"{}\" of note \"{}\" ({}), line {}", file_name, name, id, line),
+            Self::Attachment { file_name, name, id, line: None } =>
+                write!(f, "attachment \"{}\" of note \"{}\" ({})", file_name, name, id),
+        }
+    }
+}
+
+/// A single variable definition
+#[derive(Clone, Debug)]
+pub struct Var {
+    pub key: String,
+    pub value: String,
+    pub origin: Origin,
+    /// whether the value is redacted when explaining the environment
+    pub sensitive: bool,
+    /// whether the value is kept as it is instead of being interpolated
+    pub literal: bool,
+}
+
+/// The variables loaded from a single source, in the order they were defined
+#[derive(Clone, Debug)]
+pub struct Layer {
+    pub source: Source,
+    pub vars: Vec<Var>,
+}
+
+impl Layer {
+    pub fn new(source: Source, vars: Vec<Var>) -> Self {
+        Self { source, vars }
+    }
+
+    pub fn is_file(&self) -> bool {
+        matches!(self.source, Source::File(_))
+    }
+}
+
+/// The value a variable ended up with after merging, along with the definitions it replaced
+#[derive(Clone, Debug)]
+pub struct Entry {
+    pub value: String,
+    pub origin: Origin,
+    pub sensitive: bool,
+    pub literal: bool,
+    /// overwritten definitions as (value, origin), from the most recent to the oldest
+    pub shadowed: Vec<(String, Origin)>,
+}
+
+/// The merged environment, sorted by variable name
+pub type Environment = BTreeMap<String, Entry>;
+
+/// Decides which kind of source wins when the same variable is defined in both
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Precedence {
+    Files,
+    Vault,
+}
+
+impl FromStr for Precedence {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "files" => Ok(Self::Files),
+            "vault" => Ok(Self::Vault),
+            v => Err(anyhow!("Unknown precedence: {} (expected `files` or `vault`)", v))
+        }
+    }
+}
+
+/// Orders the layers in the sequence they should be applied in. Layers of the same kind keep
+/// their relative (command line) order, and the kind given by `precedence` is applied last.
+pub fn order(layers: Vec<Layer>, precedence: Precedence) -> Vec<Layer> {
+    let (files, vault): (Vec<Layer>, Vec<Layer>) = layers
+        .into_iter()
+        .partition(|l| l.is_file());
+
+    match precedence {
+        Precedence::Files => vault.into_iter().chain(files).collect(),
+        Precedence::Vault => files.into_iter().chain(vault).collect(),
+    }
+}
+
+/// Merges the layers into a single environment. Identical variables from later layers overwrite
+/// the ones from earlier layers, which are kept as shadowed definitions.
+pub fn merge(layers: Vec<Layer>, precedence: Precedence) -> Environment {
+    let mut out = Environment::new();
+
+    for var in order(layers, precedence).into_iter().flat_map(|l| l.vars) {
+        match out.get_mut(&var.key) {
+            Some(entry) => {
+                let value = std::mem::replace(&mut entry.value, var.value);
+                let origin = std::mem::replace(&mut entry.origin, var.origin);
+                entry.sensitive = var.sensitive;
+                entry.literal = var.literal;
+                entry.shadowed.insert(0, (value, origin));
+            },
+            None => {
+                out.insert(var.key, Entry {
+                    value: var.value,
+                    origin: var.origin,
+                    sensitive: var.sensitive,
+                    literal: var.literal,
+                    shadowed: Vec::new()
+                });
+            }
+        }
+    }
+
+    out
+}
+
+/// The plain values of the merged environment, sorted by name
+pub fn values(env: &Environment) -> BTreeMap<String, String> {
+    env.iter().map(|(k, e)| (k.clone(), e.value.clone())).collect()
+}
+
+/// Describes where each variable comes from and which definitions it overrides. Sensitive values
+/// are redacted.
+pub fn explain(env: &Environment) -> String {
+    let mut out = String::new();
+
+    for (key, entry) in env {
+        let value = match entry.sensitive {
+            true => "<redacted>",
+            false => entry.value.as_str()
+        };
+        out.push_str(&format!("{}={}\n    from {}\n", key, value, entry.origin));
+
+        for (_, origin) in &entry.shadowed {
+            out.push_str(&format!("    overrides {}\n", origin));
+        }
+    }
+
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn file(path: &str, vars: &[(&str, &str)]) -> Layer {
+        let vars = vars.iter().enumerate().map(|(i, (k, v))| Var {
+            key: k.to_string(),
+            value: v.to_string(),
+            origin: Origin::File { path: path.to_string(), line: Some(i + 1) },
+            sensitive: false,
+            literal: false,
+        });
+
+        Layer::new(Source::File(path.to_string()), vars.collect())
+    }
+
+    fn vault(name: &str, vars: &[(&str, &str)]) -> Layer {
+        let vars = vars.iter().map(|(k, v)| Var {
+            key: k.to_string(),
+            value: v.to_string(),
+            origin: Origin::Note { name: name.to_string(), id: format!("{}-id", name), line: None },
+            sensitive: true,
+            literal: true,
+        });
+
+        Layer::new(Source::Vault(name.to_string()), vars.collect())
+    }
+
+    /// The layers in command line order: -f a.env -b one -f b.env -b two
+    fn layers() -> Vec<Layer> {
+        vec![
+            file("a.env", &[("SHARED", "a"), ("FILES", "a")]),
+            vault("one", &[("SHARED", "one"), ("VAULT", "one")]),
+            file("b.env", &[("SHARED", "b"), ("FILES", "b")]),
+            vault("two", &[("SHARED", "two"), ("VAULT", "two")]),
+        ]
+    }
+
+    fn sources(layers: &[Layer]) -> Vec<String> {
+        layers.iter().map(|l| l.source.to_string()).collect()
+    }
+
+    #[test]
+    fn orders_by_precedence() {
+        assert_eq!(sources(&order(layers(), Precedence::Files)), [
+            "Bitwarden notes matching \"one\"",
+            "Bitwarden notes matching \"two\"",
+            "file a.env",
+            "file b.env",
+        ]);
+
+        assert_eq!(sources(&order(layers(), Precedence::Vault)), [
+            "file a.env",
+            "file b.env",
+            "Bitwarden notes matching \"one\"",
+            "Bitwarden notes matching \"two\"",
+        ]);
+    }
+
+    #[test]
+    fn files_win_by_default() {
+        let env = merge(layers(), Precedence::Files);
+
+        assert_eq!(values(&env), [("FILES", "b"), ("SHARED", "b"), ("VAULT", "two")]
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect());
+
+        let shared = &env["SHARED"];
+        assert_eq!(shared.origin, Origin::File { path: "b.env".to_string(), line: Some(1) });
+        assert!(!shared.sensitive);
+        assert!(!shared.literal);
+
+        let shadowed: Vec<&str> = shared.shadowed.iter().map(|(v, _)| v.as_str()).collect();
+        assert_eq!(shadowed, ["a", "two", "one"]);
+    }
+
+    #[test]
+    fn vault_wins_with_vault_precedence() {
+        let env = merge(layers(), Precedence::Vault);
+
+        let shared = &env["SHARED"];
+        assert_eq!(shared.value, "two");
+        assert!(shared.sensitive);
+        assert!(shared.literal);
+
+        let shadowed: Vec<&str> = shared.shadowed.iter().map(|(v, _)| v.as_str()).collect();
+        assert_eq!(shadowed, ["one", "b", "a"]);
+
+        assert_eq!(env["FILES"].value, "b");
+        assert_eq!(env["VAULT"].value, "two");
+        assert_eq!(env["VAULT"].shadowed, [("one".to_string(), Origin::Note {
+            name: "one".to_string(), id: "one-id".to_string(), line: None
+        })]);
+    }
+
+    #[test]
+    fn explains_overrides_newest_first() {
+        let layers = vec![file("a.env", &[("KEY", "a")]), file("b.env", &[("KEY", "b")])];
+        let env = merge(layers, Precedence::Files);
+
+        assert_eq!(explain(&env), "KEY=b\n    from file b.env:1\n    overrides file a.env:1\n");
+    }
+}
@@ -1,30 +1,59 @@
-use std::{collections::HashMap, process::Command};
+use std::{path::PathBuf, process::Command, time::Duration};
 
 use clap::Clap;
-use anyhow::Result;
+use anyhow::{bail, Context, Result};
 
-use bitwarden_service::{get_by_name, auth::get_token};
+use bitwarden_service::{
+    get_notes, vault_changed, auth::{get_saved_token, get_token},
+    models::{Config, ServerSettings},
+    select::{self, ItemType, MatchMode, Selector},
+};
 
 mod env;
+mod format;
+mod interpolate;
+mod layers;
+mod process;
+mod secret_file;
+mod supervisor;
+
+use format::Format;
+use glob::Pattern;
+use layers::{Environment, Layer, Origin, Precedence, Source, Var};
+use process::Signal;
+use secret_file::{FileAttachment, SecretFiles};
+use supervisor::{Envs, Restart};
+
+const DEFAULT_FILE: &str = "./.env";
 
 /// Execute a command with environment variables from .env files or
 /// Bitwarden secure notes
 #[derive(Clap)]
 #[clap(version = env!("CARGO_PKG_VERSION"), author = "xyder <xyder@dsider.org>")]
 struct Opts {
-    /// Load env vars from an .env file
-    #[clap(short, long, default_value = "./.env")]
-    file: String,
+    /// Load env vars from an .env file. Can be used multiple times, in which case the files are
+    /// merged in the order they were given and identical variables taken from the last one.
+    #[clap(short, long, default_value = "./.env", number_of_values = 1)]
+    file: Vec<String>,
 
-    /// If this is present all env sources will be merged, first loading Bitwarden,
-    /// then the .env file.
+    /// If this is present all env sources will be merged. Which kind of source wins is
+    /// decided by `precedence`.
     #[clap(short, long)]
     cumulative: bool,
 
+    /// When merging, the kind of source whose variables win over the other: `files` loads
+    /// Bitwarden first, then the .env files, while `vault` does the reverse.
+    #[clap(long, default_value = "files", possible_values = &["files", "vault"])]
+    precedence: Precedence,
+
     /// If this is present, no output will be printed (except for when printing environment variables, if needed)
     #[clap(short, long)]
     quiet: bool,
 
+    /// If this is present, the Bitwarden notes matching each `bitwarden-name` are listed on stderr
+    #[clap(short, long)]
+    verbose: bool,
+
     /// If this is present, the local cache will be used on connection errors
     #[clap(long)]
     ignore_connection_errors: bool,
@@ -33,118 +62,560 @@ struct Opts {
     #[clap(short, long)]
     list: bool,
 
+    /// The format variables are listed in: `posix` (export lines), `fish`, `nushell`, `powershell`,
+    /// `json`, `yaml`, `docker` (for --env-file), `systemd` (for EnvironmentFile) or
+    /// `github-actions` (for $GITHUB_ENV). Only `json`, `yaml` and `nushell` accept variable names
+    /// other than letters, digits and underscores.
+    #[clap(long, default_value = "posix", possible_values = &format::FORMAT_NAMES)]
+    format: Format,
+
+    /// If this is present, the source of every environment variable and the definitions it overrides
+    /// will be printed to stdout and the command will not be executed. Only the values of text,
+    /// boolean and non-secret fields are shown, everything else is redacted
+    #[clap(long)]
+    explain: bool,
+
     /// Load env vars from one or more Bitwarden secure notes. If multiple notes
     /// containing the same `bitwarden-name` are found, they will be merged in
     /// alphabetical order and identical fields overwritten.
     /// (Example:
     /// Having two notes named "MyApp environment A" and "MyApp environment B"
-    /// will cause any identical fields to be taken from "MyApp environment B")
+    /// will cause any identical fields to be taken from "MyApp environment B").
+    /// Can be used multiple times, in which case later names win over earlier ones.
+    #[clap(short, long, number_of_values = 1)]
+    bitwarden_name: Vec<String>,
+
+    /// How notes are selected by `bitwarden-name`: `contains` (ignoring case), `exact`, `prefix`,
+    /// `glob`, `regex` or `id` (the note's UUID)
+    #[clap(long = "match", default_value = "contains", possible_values = &select::MATCH_MODE_NAMES)]
+    match_mode: MatchMode,
+
+    /// If this is present, each `bitwarden-name` must match exactly one note
+    #[clap(long)]
+    strict: bool,
+
+    /// Comma separated kinds of items to load: `note`, `login`, `card` and `identity`. Besides
+    /// their custom fields, logins, cards and identities are loaded as `<PREFIX>_USERNAME`,
+    /// `<PREFIX>_PASSWORD`, `<PREFIX>_URI`, `<PREFIX>_NUMBER` and so on.
+    #[clap(long, default_value = "note", use_delimiter = true, possible_values = &select::ITEM_TYPE_NAMES)]
+    item_type: Vec<ItemType>,
+
+    /// The prefix of the variables loaded from logins, cards and identities. If this is not
+    /// present, it is made from the item name (Example: `GitHub deploy` gives `GITHUB_DEPLOY_USERNAME`)
+    #[clap(long)]
+    prefix: Option<String>,
+
+    /// If the current TOTP code of a login expires in less than this many seconds, wait for the
+    /// next one. The codes are loaded as `<PREFIX>_TOTP` and all of them are generated for the same
+    /// moment, so nxc waits at most once. (Example: `--wait-fresh 5`)
+    #[clap(long)]
+    wait_fresh: Option<u64>,
+
+    /// Load env vars from the attachments of the loaded items whose file name matches this glob
+    /// pattern, read like .env files. Can be used multiple times. (Example: `--env-attachment '*.env'`)
+    #[clap(long, number_of_values = 1)]
+    env_attachment: Vec<Pattern>,
+
+    /// Set VAR to the path of a file with the attachment of the loaded items whose file name matches
+    /// PATTERN. The file is only readable by the current user, written when the command starts and
+    /// removed when it exits. Can be used multiple times.
+    /// (Example: `--file-attachment TLS_CERT_FILE=cert.pem`)
+    #[clap(long, number_of_values = 1, value_name = "VAR=PATTERN")]
+    file_attachment: Vec<FileAttachment>,
+
+    /// Load env vars from all secure notes in a Bitwarden folder, merged in alphabetical order.
+    /// Can be used multiple times. Folders are loaded after the `bitwarden-name` notes.
+    #[clap(long, number_of_values = 1)]
+    folder: Vec<String>,
+
+    /// Load env vars from all secure notes in an organization collection, merged in alphabetical
+    /// order. Can be used multiple times. Collections are loaded after folders.
+    #[clap(long, number_of_values = 1)]
+    collection: Vec<String>,
+
+    /// If this is present, `folder` and `collection` include the folders and collections nested in
+    /// them (Example: `--folder envs/payments` also loads `envs/payments/staging`)
+    #[clap(long)]
+    recursive: bool,
+
+    /// The URL of the Bitwarden server, for self-hosted or Vaultwarden instances and other regions.
+    /// The api and identity endpoints are derived from it. (Example: `--server https://vault.bitwarden.eu`)
+    #[clap(long)]
+    server: Option<String>,
+
+    /// The URL of the Bitwarden api endpoint, if it's not `<server>/api`
+    #[clap(long)]
+    api_url: Option<String>,
+
+    /// The URL of the Bitwarden identity endpoint, if it's not `<server>/identity`
+    #[clap(long)]
+    identity_url: Option<String>,
+
+    /// The shell to run this command in (Example: `-s /bin/sh`). If this is not present, nxc is
+    /// replaced by the command itself and its arguments are passed through untouched.
+    #[clap(short, long)]
+    shell: Option<String>,
+
+    /// If this is present, `${VAR}`, `${VAR:-default}` and `${VAR:?error}` references in values
+    /// will not be expanded
+    #[clap(long)]
+    no_interpolation: bool,
+
+    /// Keep the values of variables matching this glob pattern literal, without expanding
+    /// references. Can be used multiple times. (Example: `--literal 'PASSWORD_*'`)
+    #[clap(long, number_of_values = 1)]
+    literal: Vec<Pattern>,
+
+    /// If this is present, the command will not inherit any environment variables from nxc except
+    /// the ones allowed by `keep`
+    #[clap(long)]
+    clean_env: bool,
+
+    /// Comma separated names of the variables that are still inherited when using `clean-env`.
+    /// Glob patterns are accepted. (Example: `--keep PATH,HOME,TERM,LC_*`)
+    #[clap(long, requires = "clean-env", use_delimiter = true)]
+    keep: Vec<Pattern>,
+
+    /// Do not pass on inherited variables matching this glob pattern. Can be used multiple times.
+    /// Loaded variables are not affected. (Example: `--unset 'AWS_*'`)
+    #[clap(long, number_of_values = 1)]
+    unset: Vec<Pattern>,
+
+    /// If this is present, the .env files are watched and the command is restarted when they change
+    /// and the resulting environment is different
     #[clap(short, long)]
-    bitwarden_name: Option<String>,
+    watch: bool,
+
+    /// The signal sent to the command to stop it before restarting it
+    #[clap(long, default_value = "TERM")]
+    stop_signal: Signal,
+
+    /// Seconds the command has to stop before it is killed with SIGKILL
+    #[clap(long, default_value = "10")]
+    grace_period: u64,
 
-    /// The shell to run this command in.
-    #[clap(short, long, default_value = "/bin/sh")]
-    shell: String,
+    /// Milliseconds to wait for further file changes before restarting the command
+    #[clap(long, default_value = "300")]
+    debounce: u64,
+
+    /// If this is present, the Bitwarden vault is checked for changes every `poll-vault` seconds
+    /// while the command runs. The command is restarted when the loaded variables changed.
+    #[clap(long)]
+    poll_vault: Option<u64>,
+
+    /// Send this signal to the command instead of restarting it when the environment changes
+    /// (Example: `--reload-signal HUP`). Note that the environment of a running command can't be
+    /// changed, so it has to load the new values by itself (for example with `nxc --list`).
+    #[clap(long)]
+    reload_signal: Option<Signal>,
+
+    /// Whether the command is started again after it exits: `never`, `on-failure` (non-zero exit
+    /// code or killed by a signal) or `always`. The environment is loaded again before each restart.
+    #[clap(long, default_value = "never", possible_values = &["never", "on-failure", "always"])]
+    restart: Restart,
+
+    /// The maximum number of consecutive restarts. Unlimited if not present.
+    #[clap(long)]
+    max_retries: Option<u32>,
+
+    /// Milliseconds to wait before the first restart. The delay doubles with each consecutive
+    /// restart and is randomly reduced by up to half.
+    #[clap(long, default_value = "1000")]
+    backoff: u64,
+
+    /// The maximum number of seconds to wait before a restart
+    #[clap(long, default_value = "60")]
+    max_backoff: u64,
+
+    /// Seconds the command has to run for before its consecutive restarts are reset
+    #[clap(long, default_value = "10")]
+    success_threshold: u64,
 
     /// the command to run
     command: Vec<String>,
 }
 
-fn bw_get_by_name(name: &str, ignore_conn_errors: bool, quiet: bool) -> Result<HashMap<String, String>> {
-    let token = get_token(ignore_conn_errors, quiet)?;
-    get_by_name(name, &token, ignore_conn_errors, quiet).map_err(|e|e.into())
+fn uses_vault(opts: &Opts) -> bool {
+    !opts.bitwarden_name.is_empty() || !opts.folder.is_empty() || !opts.collection.is_empty()
 }
 
-fn main() {
-    let opts = Opts::parse();
-    let quiet = opts.quiet || opts.list;
-    let ignore_conn_errors = opts.ignore_connection_errors;
+/// Loads the Bitwarden configuration. Server settings given on the command line win over the ones
+/// from the environment and the config file, as a whole: they are never mixed.
+fn load_config(opts: &Opts) -> Result<Config> {
+    let mut config = Config::load(false)?;
 
-    if !opts.list && opts.command.len() == 0 {
-        if !quiet {
-            println!("Error: No command supplied.");
+    let cli = ServerSettings {
+        server: opts.server.clone(),
+        api_url: opts.api_url.clone(),
+        identity_url: opts.identity_url.clone(),
+    };
+    config.server = cli.or(config.server);
+
+    Ok(config)
+}
+
+fn bw_get_layers(opts: &Opts, config: &Config, files: &SecretFiles, quiet: bool) -> Result<Vec<Layer>> {
+    let mut sources: Vec<Source> = Vec::new();
+    let mut selectors: Vec<Selector> = Vec::new();
+
+    for name in &opts.bitwarden_name {
+        sources.push(Source::Vault(name.clone()));
+        selectors.push(Selector::new(opts.match_mode, name)?);
+    }
+    for name in &opts.folder {
+        sources.push(Source::Folder(name.clone()));
+        selectors.push(Selector::folder(name, opts.recursive));
+    }
+    for name in &opts.collection {
+        sources.push(Source::Collection(name.clone()));
+        selectors.push(Selector::collection(name, opts.recursive));
+    }
+
+    let token = get_token(config, opts.ignore_connection_errors, quiet)?;
+    let options = select::Options {
+        item_types: opts.item_type.clone(),
+        prefix: opts.prefix.clone(),
+        wait_fresh: opts.wait_fresh,
+        attachments: opts.env_attachment
+            .iter()
+            .chain(opts.file_attachment.iter().map(|a| &a.pattern))
+            .cloned()
+            .collect(),
+    };
+    let found = get_notes(&selectors, &options, config, &token, opts.ignore_connection_errors, quiet)?;
+
+    for (source, notes) in sources.iter().zip(&found) {
+        if opts.verbose {
+            eprintln!("{}:", source);
+            for note in notes {
+                eprintln!("    {} ({})", note.name, note.id);
+            }
         }
-        std::process::exit(2);
 
+        match source {
+            Source::Vault(name) if opts.strict && notes.len() != 1 => {
+                bail!("Expected exactly one note matching \"{}\", found {}{}", name, notes.len(),
+                    match notes.len() {
+                        0 => String::new(),
+                        _ => format!(": {}", notes.iter().map(|n| n.name.as_str()).collect::<Vec<&str>>().join(", "))
+                    });
+            },
+            _ => ()
+        }
     }
 
-    let bw_envs = match &opts.bitwarden_name {
-        Some(v) => {
-            bw_get_by_name(&v, ignore_conn_errors, quiet).unwrap_or_else(|err| {
-                // surface any bw error
-                eprintln!("{:#}", err);
-                std::process::exit(2);
-            })
-        },
-        None => HashMap::new()
+    let mut layers = Vec::new();
+
+    for (source, notes) in sources.into_iter().zip(found) {
+        let mut vars = Vec::new();
+
+        for note in notes {
+            vars.extend(note_vars(note, opts, files)?);
+        }
+
+        layers.push(Layer::new(source, vars));
+    }
+
+    Ok(layers)
+}
+
+/// The variables of a note. The body (if it is valid) and the `env-attachment` attachments are
+/// parsed as dotenv content, and `file-attachment` attachments are staged to be written to files
+/// whose paths are set. Custom fields with the same name win over all of them. Variables from the
+/// body and from attachments are treated as sensitive.
+fn note_vars(note: bitwarden_service::models::Note, opts: &Opts, files: &SecretFiles) -> Result<Vec<Var>> {
+    let id = note.id.to_string();
+    let origin = |line| Origin::Note { name: note.name.clone(), id: id.clone(), line };
+    let attachment_origin = |file_name: &str, line| Origin::Attachment {
+        file_name: file_name.to_string(), name: note.name.clone(), id: id.clone(), line };
+
+    // notes written as plain text are common, so a body that isn't dotenv content is skipped
+    let body = match &note.body {
+        Some(v) => env::parse(v).unwrap_or_else(|err| {
+            if !opts.quiet {
+                eprintln!("Skipping the body of note \"{}\" ({}), it is not valid .env content{}: {:#}",
+                    note.name, id,
+                    env::error_line(v).map(|l| format!(" (line {})", l)).unwrap_or_default(),
+                    err);
+            }
+            Vec::new()
+        }),
+        None => Vec::new()
+    };
+
+    let mut vars: Vec<Var> = body
+        .into_iter()
+        .map(|v| Var { key: v.key, value: v.value, origin: origin(v.line), sensitive: true, literal: false })
+        .collect();
+
+    for attachment in &note.attachments {
+        if !opts.env_attachment.iter().any(|p| p.matches(&attachment.file_name)) {
+            continue;
+        }
+
+        let context = || format!("Could not parse attachment \"{}\" of note \"{}\" ({})",
+            attachment.file_name, note.name, id);
+        let contents = String::from_utf8(attachment.data.clone()).context(context())?;
+
+        vars.extend(env::parse(&contents)
+            .context(context())?
+            .into_iter()
+            .map(|v| Var {
+                key: v.key,
+                value: v.value,
+                origin: attachment_origin(&attachment.file_name, v.line),
+                sensitive: true,
+                literal: false
+            }));
+    }
+
+    for file in &opts.file_attachment {
+        for attachment in note.attachments.iter().filter(|a| file.pattern.matches(&a.file_name)) {
+            let path = files.stage(&id, &file.var, &attachment.file_name, &attachment.data);
+
+            vars.push(Var {
+                key: file.var.clone(),
+                value: path.to_string_lossy().to_string(),
+                origin: attachment_origin(&attachment.file_name, None),
+                sensitive: false,
+                literal: false
+            });
+        }
+    }
+
+    vars.extend(note.fields
+        .iter()
+        .map(|f| Var {
+            key: f.name.clone(),
+            value: f.value.clone(),
+            origin: origin(None),
+            sensitive: f.sensitive,
+            literal: f.literal
+        }));
+
+    Ok(vars)
+}
+
+fn load_file(path: &str, quiet: bool) -> Result<Layer> {
+    let vars = match env::get_env_vars(path) {
+        Ok(v) => v,
+        Err(err) => match err.downcast_ref::<std::io::Error>() {
+            Some(e) if (e.kind() == std::io::ErrorKind::NotFound) && (path == DEFAULT_FILE) => {
+                // didn't find ./.env
+                if !quiet {
+                    println!("No file envs loaded.")
+                };
+                Vec::new()
+            },
+            // surface any other error
+            _ => return Err(err)
+        }
+    };
+
+    let vars = vars
+        .into_iter()
+        .map(|v| Var {
+            key: v.key,
+            value: v.value,
+            origin: Origin::File { path: path.to_string(), line: v.line },
+            sensitive: true,
+            literal: false
+        })
+        .collect();
+
+    Ok(Layer::new(Source::File(path.to_string()), vars))
+}
+
+/// Loads all sources and merges them into the final environment
+fn resolve(opts: &Opts, files: &SecretFiles, quiet: bool) -> Result<Environment> {
+    files.clear();
+
+    let bw_layers = match uses_vault(opts) {
+        false => Vec::new(),
+        true => bw_get_layers(opts, &load_config(opts)?, files, quiet)?
     };
 
-    if bw_envs.len() == 0 {
+    let bw_loaded = bw_layers.iter().any(|l| !l.vars.is_empty());
+    if !bw_loaded {
         if !quiet {
             println!("No BW envs loaded.")
         }
     }
 
-    let file_envs = env::get_env_vars(&opts.file)
-        .unwrap_or_else(|err| {
-            match err.downcast_ref::<std::io::Error>() {
-                Some(e) => if (e.kind() == std::io::ErrorKind::NotFound) && (opts.file == "./.env") {
-                    // didn't find ./.env
-                    if !quiet {
-                        println!("No file envs loaded.")
-                    };
-                    HashMap::new()
-                } else {
-                    // surface io::Error
-                    eprintln!("{:#}", err);
-                    std::process::exit(2);
-                },
-                None => {
-                    // surface any other error
-                    eprintln!("{:#}", err);
-                    std::process::exit(2)
-                }
-            }
-        });
+    let file_layers = opts.file
+        .iter()
+        .map(|path| load_file(path, quiet))
+        .collect::<Result<Vec<Layer>>>()?;
 
-    let envs = if opts.cumulative {
-        let mut res: HashMap<String, String> = HashMap::new();
-        res.extend(bw_envs);
-        res.extend(file_envs);
-        res
+    let layers = if opts.cumulative {
+        bw_layers.into_iter().chain(file_layers).collect()
     } else {
-        if bw_envs.len() != 0 {
-            bw_envs
+        if bw_loaded {
+            bw_layers
         } else {
-            file_envs
+            file_layers
         }
     };
 
+    if !quiet {
+        for layer in &layers {
+            println!("Loaded {} variables from {}.", layer.vars.len(), layer.source);
+        }
+    }
+
+    let mut resolved = layers::merge(layers, opts.precedence);
+
+    if !opts.no_interpolation {
+        interpolate::interpolate(&mut resolved, &opts.literal, &inherit(opts).inherited())?;
+    }
+
+    Ok(resolved)
+}
+
+fn inherit(opts: &Opts) -> process::Inherit {
+    process::Inherit { clean: opts.clean_env, keep: opts.keep.clone(), unset: opts.unset.clone() }
+}
+
+/// Builds the command with the given environment, writing the attachment files it uses
+fn build_command(opts: &Opts, envs: &Envs, files: &SecretFiles) -> Result<Command> {
+    files.write()?;
+    let inherit = inherit(opts);
+
+    let mut command = match &opts.shell {
+        Some(shell) => {
+            let mut command = Command::new(shell);
+            command
+                .arg("-c")
+                .arg(opts.command.join(" "));
+            command
+        },
+        None => {
+            let mut command = Command::new(&opts.command[0]);
+            command.args(&opts.command[1..]);
+            command
+        }
+    };
+
+    // runs the command with stdin, stdout and stderr inherited from the parent
+    inherit.apply(&mut command, envs);
+
+    Ok(command)
+}
+
+fn main() {
+    let opts = Opts::parse();
+    let quiet = opts.quiet || opts.list || opts.explain;
+
+    if !opts.list && !opts.explain && opts.command.len() == 0 {
+        if !quiet {
+            println!("Error: No command supplied.");
+        }
+        std::process::exit(2);
+
+    }
+
+    let files = SecretFiles::default();
+    let resolved = resolve(&opts, &files, quiet).unwrap_or_else(|err| {
+        // surface any error
+        eprintln!("{:#}", err);
+        std::process::exit(2);
+    });
+    let envs = layers::values(&resolved);
+
     if !quiet {
         println!("Loaded {} environment variables.", envs.len());
     }
 
+    if opts.explain {
+        print!("{}", layers::explain(&resolved));
+
+        return
+    }
+
     if opts.list {
-        for (env_key, env_val) in envs {
-            println!("{}='{}'", env_key, env_val);
+        match format::render(opts.format, &envs) {
+            Ok(v) => print!("{}", v),
+            Err(e) => {
+                eprintln!("{:#}", e);
+                std::process::exit(2);
+            }
         }
 
         return
     }
 
-    let output = Command::new(opts.shell)
-        .arg("-c")
-        .arg(opts.command.join(" "))
-        .envs(&envs)
-        // runs the command with stdin, stdout and stderr inherited from the parent
-        // alternatively can be run with Stdio::inherit
-        .status();
+    if opts.watch || opts.poll_vault.is_some() || opts.restart != Restart::Never {
+        let options = supervisor::Options {
+            stop_signal: opts.stop_signal.0,
+            grace_period: Duration::from_secs(opts.grace_period),
+            debounce: Duration::from_millis(opts.debounce),
+            reload_signal: opts.reload_signal.map(|s| s.0),
+            poll_interval: opts.poll_vault.map(Duration::from_secs),
+            restart: supervisor::RestartPolicy {
+                restart: opts.restart,
+                max_retries: opts.max_retries,
+                backoff: Duration::from_millis(opts.backoff),
+                max_backoff: Duration::from_secs(opts.max_backoff),
+                success_threshold: Duration::from_secs(opts.success_threshold),
+            },
+            quiet,
+        };
+        let watched: Vec<PathBuf> = match opts.watch {
+            true => opts.file.iter().map(PathBuf::from).collect(),
+            false => Vec::new()
+        };
+        let mut reload = || resolve(&opts, &files, quiet).map(|r| layers::values(&r));
+        // polled on another thread while the command owns the terminal, so it never prompts
+        let check_vault: Box<dyn FnMut() -> Result<bool> + Send> = match uses_vault(&opts) {
+            true => {
+                let config = load_config(&opts).unwrap_or_else(|err| {
+                    eprintln!("{:#}", err);
+                    std::process::exit(2);
+                });
+                let ignore_connection_errors = opts.ignore_connection_errors;
 
-    if output.is_err() {
-        let e = output.unwrap_err();
-        if !quiet {
-            println!("{:?} error: {}", e.kind(), e);
+                Box::new(move || {
+                    let token = get_saved_token(&config, ignore_connection_errors)?;
+                    vault_changed(&config, &token)
+                })
+            },
+            false => Box::new(|| Ok(false))
+        };
+
+        let result = supervisor::supervise(
+            envs, &mut reload, &|e| build_command(&opts, e, &files), &|| files.write(), &watched,
+            check_vault, &options);
+        files.remove();
+
+        match result {
+            Ok(code) => std::process::exit(code),
+            Err(e) => {
+                if !quiet {
+                    println!("{:#}", e);
+                }
+                std::process::exit(1);
+            }
+        }
+    }
+
+    let command = build_command(&opts, &envs, &files).unwrap_or_else(|err| {
+        eprintln!("{:#}", err);
+        std::process::exit(2);
+    });
+    // nxc can't remove the attachment files once it is replaced by the command, so it waits for it
+    let result = match opts.shell.is_some() || !opts.file_attachment.is_empty() {
+        true => process::run(command),
+        false => Err(process::exec(command))
+    };
+    files.remove();
+
+    match result {
+        Ok(code) => std::process::exit(code),
+        Err(e) => {
+            if !quiet {
+                println!("{:?} error: {}", e.kind(), e);
+            }
+            std::process::exit(1);
         }
-        std::process::exit(1);
     }
 }
@@ -0,0 +1,270 @@
+use std::{collections::BTreeMap, env, ffi::OsStr, io, thread, process::{Child, Command, ExitStatus}, str::FromStr};
+use std::os::unix::process::{CommandExt, ExitStatusExt};
+
+use anyhow::{anyhow, Error};
+use glob::Pattern;
+use signal_hook::{
+    consts::signal::*,
+    iterator::{SignalsInfo, exfiltrator::WithOrigin},
+    low_level::siginfo::{Cause, Origin},
+};
+
+/// Signals received by nxc that are relayed to the child
+pub const FORWARDED_SIGNALS: [i32; 4] = [SIGINT, SIGTERM, SIGHUP, SIGQUIT];
+
+
+/// A signal given by name (`TERM`, `SIGTERM`) or by number (`15`)
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Signal(pub i32);
+
+impl FromStr for Signal {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if let Ok(v) = s.parse::<i32>() {
+            return Ok(Self(v));
+        }
+
+        let name = s.to_uppercase();
+        let name = name.strip_prefix("SIG").unwrap_or(&name);
+
+        Ok(Self(match name {
+            "HUP" => SIGHUP,
+            "INT" => SIGINT,
+            "QUIT" => SIGQUIT,
+            "KILL" => SIGKILL,
+            "USR1" => SIGUSR1,
+            "USR2" => SIGUSR2,
+            "TERM" => SIGTERM,
+            _ => return Err(anyhow!("Unknown signal: {}", s))
+        }))
+    }
+}
+
+
+/// Decides which of the variables nxc inherited are passed on to the command
+pub struct Inherit {
+    /// if set, only the inherited variables matching `keep` are passed on
+    pub clean: bool,
+    pub keep: Vec<Pattern>,
+    /// inherited variables matching any of these are never passed on
+    pub unset: Vec<Pattern>,
+}
+
+impl Inherit {
+    fn allows(&self, key: &OsStr) -> bool {
+        let key = key.to_string_lossy();
+
+        if self.clean && !self.keep.iter().any(|p| p.matches(&key)) {
+            return false;
+        }
+
+        !self.unset.iter().any(|p| p.matches(&key))
+    }
+
+    /// The inherited variables that are allowed. Variables that aren't valid unicode are left out.
+    pub fn inherited(&self) -> BTreeMap<String, String> {
+        env::vars_os()
+            .filter(|(k, _)| self.allows(k))
+            .filter_map(|(k, v)| Some((k.into_string().ok()?, v.into_string().ok()?)))
+            .collect()
+    }
+
+    /// Sets up the environment of the command: the inherited variables that are allowed,
+    /// overwritten by the loaded ones. Inherited values are passed on as they are, even if they
+    /// aren't valid unicode.
+    pub fn apply(&self, command: &mut Command, loaded: &BTreeMap<String, String>) {
+        if self.clean {
+            command.env_clear();
+            command.envs(env::vars_os().filter(|(k, _)| self.allows(k)));
+        } else {
+            for (key, _) in env::vars_os().filter(|(k, _)| !self.allows(k)) {
+                command.env_remove(key);
+            }
+        }
+
+        command.envs(loaded);
+    }
+}
+
+/// Converts the exit status of the child into the exit code nxc should exit with.
+/// A child killed by a signal results in 128 + signal number, the same as a shell would report.
+pub fn exit_code(status: ExitStatus) -> i32 {
+    match status.code() {
+        Some(v) => v,
+        None => 128 + status.signal().unwrap_or(0)
+    }
+}
+
+/// Holds the terminal's foreground process group while the child runs and gives it back to nxc
+/// when dropped.
+pub struct Foreground {
+    previous: libc::pid_t
+}
+
+impl Foreground {
+    /// Makes `pgid` the foreground process group of the controlling terminal, if nxc currently owns it.
+    pub fn acquire(pgid: libc::pid_t) -> Option<Self> {
+        unsafe {
+            if libc::isatty(libc::STDIN_FILENO) == 0 {
+                return None;
+            }
+
+            let previous = libc::tcgetpgrp(libc::STDIN_FILENO);
+            if previous != libc::getpgrp() {
+                // running in the background, there is nothing to hand over
+                return None;
+            }
+
+            // tcsetpgrp from a process that is no longer in the foreground raises SIGTTOU
+            libc::signal(libc::SIGTTOU, libc::SIG_IGN);
+            libc::tcsetpgrp(libc::STDIN_FILENO, pgid);
+
+            Some(Self { previous })
+        }
+    }
+}
+
+impl Drop for Foreground {
+    fn drop(&mut self) {
+        unsafe {
+            libc::tcsetpgrp(libc::STDIN_FILENO, self.previous);
+            libc::signal(libc::SIGTTOU, libc::SIG_DFL);
+        }
+    }
+}
+
+/**
+Whether nxc runs on its own in the foreground of a terminal: stdin and stdout are a terminal and
+nxc's process group owns it. In a pipeline (`nxc ... | less`) the other commands are in the same
+process group, so handing the terminal over to the child would stop them. A pipeline that only
+redirects stderr is not detected.
+*/
+fn owns_terminal() -> bool {
+    unsafe {
+        libc::isatty(libc::STDIN_FILENO) == 1
+            && libc::isatty(libc::STDOUT_FILENO) == 1
+            && libc::tcgetpgrp(libc::STDIN_FILENO) == libc::getpgrp()
+    }
+}
+
+/// Places the child in its own process group and gives that group the terminal, if nxc owns it.
+/// This is done in the child as well as in the parent to avoid racing the child's first terminal
+/// read. Otherwise the child stays in nxc's process group, and this returns false.
+fn isolate(command: &mut Command) -> bool {
+    if !owns_terminal() {
+        return false;
+    }
+
+    unsafe {
+        command.pre_exec(|| {
+            if libc::setpgid(0, 0) != 0 {
+                return Err(io::Error::last_os_error());
+            }
+
+            if libc::tcgetpgrp(libc::STDIN_FILENO) == libc::getpgid(libc::getppid()) {
+                libc::signal(libc::SIGTTOU, libc::SIG_IGN);
+                libc::tcsetpgrp(libc::STDIN_FILENO, libc::getpid());
+                libc::signal(libc::SIGTTOU, libc::SIG_DFL);
+            }
+
+            Ok(())
+        });
+    }
+
+    true
+}
+
+/// A signal received by nxc
+#[derive(Clone, Copy, Debug)]
+pub struct Received {
+    pub signal: i32,
+    /// sent by the kernel rather than by a process, as the terminal does for Ctrl-C and Ctrl-\ to
+    /// its whole foreground process group
+    pub by_kernel: bool,
+}
+
+impl From<Origin> for Received {
+    fn from(origin: Origin) -> Self {
+        Self { signal: origin.signal, by_kernel: origin.cause == Cause::Kernel }
+    }
+}
+
+/// Listens for the signals nxc forwards, along with who sent them
+pub fn signals() -> io::Result<SignalsInfo<WithOrigin>> {
+    SignalsInfo::<WithOrigin>::new(FORWARDED_SIGNALS)
+}
+
+/// Where the signals meant for a command are sent: its process group if it was isolated,
+/// otherwise only the command, since nxc's process group may hold other commands of a pipeline
+#[derive(Clone, Copy, Debug)]
+pub struct Target {
+    pid: libc::pid_t,
+    isolated: bool,
+}
+
+impl Target {
+    pub fn signal(self, signal: i32) {
+        let target = if self.isolated { -self.pid } else { self.pid };
+        unsafe { libc::kill(target, signal); }
+    }
+
+    /// Relays a signal nxc received. A signal the terminal sent to nxc's process group already
+    /// reached a command that shares it, so it is not sent twice. Signals sent to nxc itself, by
+    /// `kill` or a container runtime, are always relayed.
+    pub fn forward(self, received: Received) {
+        if self.isolated || !received.by_kernel {
+            self.signal(received.signal);
+        }
+    }
+}
+
+/// A running command
+pub struct Spawned {
+    pub child: Child,
+    pub target: Target,
+    pub foreground: Option<Foreground>,
+}
+
+impl Spawned {
+    /// Spawns the command, in its own process group and with the terminal if nxc owns it
+    pub fn new(mut command: Command) -> io::Result<Self> {
+        let isolated = isolate(&mut command);
+        let child = command.spawn()?;
+        let pid = child.id() as libc::pid_t;
+        let foreground = if isolated { Foreground::acquire(pid) } else { None };
+
+        Ok(Self { child, target: Target { pid, isolated }, foreground })
+    }
+}
+
+/// Replaces the nxc process with the command (execvp), keeping its arguments as they are.
+/// This only returns if the command could not be executed.
+pub fn exec(mut command: Command) -> io::Error {
+    command.exec()
+}
+
+/// Runs the command to completion and returns the exit code nxc should exit with.
+/// SIGINT, SIGTERM, SIGHUP and SIGQUIT received in the meantime are forwarded to the child, unless
+/// the terminal already sent them to it (see `Target::forward`).
+pub fn run(command: Command) -> io::Result<i32> {
+    // register the handlers before spawning so no signal is lost in between
+    let mut signals = signals()?;
+    let handle = signals.handle();
+
+    let Spawned { mut child, target, foreground } = Spawned::new(command)?;
+
+    let forwarder = thread::spawn(move || {
+        for origin in signals.forever() {
+            target.forward(origin.into());
+        }
+    });
+
+    let status = child.wait();
+
+    handle.close();
+    forwarder.join().unwrap_or(());
+    drop(foreground);
+
+    Ok(exit_code(status?))
+}
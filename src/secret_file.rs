@@ -0,0 +1,155 @@
+use std::{
+    cell::RefCell, collections::BTreeMap, env, fs, str::FromStr,
+    io::Write,
+    os::unix::fs::{DirBuilderExt, MetadataExt, OpenOptionsExt, PermissionsExt},
+    path::{Path, PathBuf},
+};
+
+use anyhow::{Context, Error, Result, anyhow, bail};
+use glob::Pattern;
+
+
+/// An attachment written to a file, whose path is set as `var` (`--file-attachment VAR=PATTERN`)
+#[derive(Clone, Debug)]
+pub struct FileAttachment {
+    pub var: String,
+    /// matches the file name of the attachment
+    pub pattern: Pattern,
+}
+
+impl FromStr for FileAttachment {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (var, pattern) = match s.find('=') {
+            Some(i) if i > 0 => (&s[..i], &s[i + 1..]),
+            _ => return Err(anyhow!("Expected VAR=PATTERN, found: {}", s))
+        };
+
+        Ok(Self {
+            var: var.to_string(),
+            pattern: Pattern::new(pattern).context(format!("Invalid glob pattern: {}", pattern))?,
+        })
+    }
+}
+
+/// The directory secret files are written to: `$XDG_RUNTIME_DIR/nxcmdr` if it is set (usually
+/// in memory and removed on logout), otherwise `nxcmdr-<uid>` in the temp directory.
+fn secret_dir() -> PathBuf {
+    match env::var_os("XDG_RUNTIME_DIR") {
+        Some(v) if !v.is_empty() => PathBuf::from(v).join("nxcmdr"),
+        _ => env::temp_dir().join(format!("nxcmdr-{}", unsafe { libc::getuid() }))
+    }
+}
+
+/// Creates the directory secret files are written to, if needed, and checks that it is only
+/// accessible by the current user
+fn ensure_dir(dir: &Path) -> Result<()> {
+    let uid = unsafe { libc::getuid() };
+    let dir_str = dir.to_str().unwrap_or("");
+
+    if !dir.exists() {
+        fs::DirBuilder::new()
+            .mode(0o700)
+            .create(dir)
+            .context(format!("Could not create directory {}", dir_str))?;
+    }
+
+    // the directory could have been created by someone else, the temp directory is shared
+    let metadata = fs::symlink_metadata(dir)
+        .context(format!("Could not read directory {}", dir_str))?;
+    if !metadata.is_dir() || metadata.uid() != uid || metadata.permissions().mode() & 0o077 != 0 {
+        bail!("{} must be a directory owned by the current user and accessible only by them", dir_str);
+    }
+
+    Ok(())
+}
+
+/// Writes `data` to a file only the current user can read. The file is replaced in one go, so a
+/// running command never reads a half written file.
+fn write(path: &Path, data: &[u8]) -> Result<()> {
+    let path_str = path.to_str().unwrap_or("");
+    let mut partial = path.as_os_str().to_owned();
+    partial.push(".part");
+
+    let _ = fs::remove_file(&partial);
+    fs::OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .mode(0o600)
+        .open(&partial)
+        .context(format!("Could not create file {}", path_str))?
+        .write_all(data)
+        .context(format!("Could not write to file {}", path_str))?;
+    fs::rename(&partial, path)
+        .context(format!("Could not write to file {}", path_str))?;
+
+    Ok(())
+}
+
+/**
+The attachments that are written to files for the command (`--file-attachment`). Loading the
+variables only stages them, the files are written right before the command is started, so listing
+or explaining the variables writes nothing. The files are removed once the command exits.
+*/
+#[derive(Default)]
+pub struct SecretFiles {
+    staged: RefCell<BTreeMap<PathBuf, Vec<u8>>>,
+    /// the files written so far, with their contents
+    written: RefCell<BTreeMap<PathBuf, Vec<u8>>>,
+}
+
+impl SecretFiles {
+    /// Forgets the staged files, before the variables are loaded again
+    pub fn clear(&self) {
+        self.staged.borrow_mut().clear();
+    }
+
+    /// Stages an attachment for the variable `var` and returns the path it will be written to. The
+    /// path doesn't depend on the attachment id, so an attachment replaced in the vault by a file
+    /// with the same name is written to the same path, which a running command can read again.
+    pub fn stage(&self, note_id: &str, var: &str, file_name: &str, data: &[u8]) -> PathBuf {
+        let file_name: String = file_name
+            .chars()
+            .map(|c| if c.is_ascii_alphanumeric() || c == '.' || c == '-' || c == '_' { c } else { '_' })
+            .collect();
+
+        let path = secret_dir().join(format!("{}-{}-{}", note_id, var, file_name));
+        self.staged.borrow_mut().insert(path.clone(), data.to_vec());
+
+        path
+    }
+
+    /// Writes the staged files. Returns whether any of them is new or has other contents than
+    /// when it was last written.
+    pub fn write(&self) -> Result<bool> {
+        let staged = self.staged.borrow();
+        if staged.is_empty() {
+            return Ok(false);
+        }
+
+        ensure_dir(&secret_dir())?;
+
+        let mut written = self.written.borrow_mut();
+        let mut changed = false;
+
+        for (path, data) in staged.iter() {
+            write(path, data)?;
+            if written.insert(path.clone(), data.clone()).as_ref() != Some(data) {
+                changed = true;
+            }
+        }
+
+        Ok(changed)
+    }
+
+    /// Removes the files written so far
+    pub fn remove(&self) {
+        let mut written = self.written.borrow_mut();
+
+        for path in written.keys() {
+            let _ = fs::remove_file(path);
+        }
+        written.clear();
+    }
+}
@@ -0,0 +1,388 @@
+use std::{collections::{BTreeMap, HashMap}, ffi::OsString, path::{Path, PathBuf}, str::FromStr, thread};
+use std::process::{Child, Command, ExitStatus};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender};
+use std::time::{Duration, Instant};
+
+use anyhow::{anyhow, Context, Error, Result};
+use inotify::{Inotify, WatchMask};
+use rand::Rng;
+use crate::process::{self, Foreground, Received, Spawned, Target};
+
+/// How often the child is checked for having exited
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+pub type Envs = BTreeMap<String, String>;
+
+enum Event {
+    /// one of the watched files changed
+    Changed,
+    /// polling found that the vault changed
+    VaultChanged,
+    /// nxc received a signal that should be forwarded
+    Signal(Received),
+}
+
+/// When the command is started again after it exits on its own
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Restart {
+    Never,
+    OnFailure,
+    Always,
+}
+
+impl FromStr for Restart {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "never" => Ok(Self::Never),
+            "on-failure" => Ok(Self::OnFailure),
+            "always" => Ok(Self::Always),
+            v => Err(anyhow!("Unknown restart policy: {} (expected `never`, `on-failure` or `always`)", v))
+        }
+    }
+}
+
+/// Decides if and when an exited command is restarted
+pub struct RestartPolicy {
+    pub restart: Restart,
+    /// the number of consecutive restarts allowed, unlimited if not set
+    pub max_retries: Option<u32>,
+    /// delay before the first restart, doubled for each consecutive one
+    pub backoff: Duration,
+    pub max_backoff: Duration,
+    /// a command that ran for at least this long resets the count of consecutive restarts
+    pub success_threshold: Duration,
+}
+
+impl RestartPolicy {
+    fn applies_to(&self, status: &ExitStatus) -> bool {
+        match self.restart {
+            Restart::Never => false,
+            Restart::OnFailure => !status.success(),
+            Restart::Always => true,
+        }
+    }
+
+    /// The delay before restart number `attempt` (starting at 1): exponential, capped at
+    /// `max_backoff` and randomly reduced by up to half to avoid restarting in lockstep.
+    fn delay(&self, attempt: u32) -> Duration {
+        let factor = 2u32.saturating_pow(attempt.saturating_sub(1));
+        let delay = self.backoff
+            .checked_mul(factor)
+            .unwrap_or(self.max_backoff)
+            .min(self.max_backoff);
+
+        delay.mul_f64(rand::thread_rng().gen_range(0.5, 1.0))
+    }
+}
+
+pub struct Options {
+    /// signal sent to the command to stop it before a restart
+    pub stop_signal: i32,
+    /// time the command has to stop before it is killed
+    pub grace_period: Duration,
+    /// time to wait for further changes before reloading
+    pub debounce: Duration,
+    /// if set, the command receives this signal instead of being restarted when the environment changes
+    pub reload_signal: Option<i32>,
+    /// how often the vault is checked for changes, if at all
+    pub poll_interval: Option<Duration>,
+    pub restart: RestartPolicy,
+    pub quiet: bool,
+}
+
+/// Signals that mean nxc itself should stop, so the command is not restarted afterwards
+fn is_terminating(signal: i32) -> bool {
+    [libc::SIGINT, libc::SIGTERM, libc::SIGQUIT].contains(&signal)
+}
+
+/// Whether two environments differ in more than their TOTP codes, which change every few seconds
+/// and would otherwise make every check look like a change
+fn differs(a: &Envs, b: &Envs) -> bool {
+    fn stable(envs: &Envs) -> impl Iterator<Item = (&String, &String)> {
+        envs.iter().filter(|(k, _)| *k != "TOTP" && !k.ends_with("_TOTP"))
+    }
+
+    !stable(a).eq(stable(b))
+}
+
+/// A running command
+struct Running {
+    child: Child,
+    target: Target,
+    foreground: Option<Foreground>,
+}
+
+impl Running {
+    fn spawn(command: Command) -> Result<Self> {
+        let Spawned { child, target, foreground } = Spawned::new(command)
+            .context("Could not run command")?;
+
+        Ok(Self { child, target, foreground })
+    }
+
+    fn signal(&self, signal: i32) {
+        self.target.signal(signal);
+    }
+
+    /// Sends `signal` to the command and kills it if it's still running after `grace_period`
+    fn stop(&mut self, signal: i32, grace_period: Duration) -> Result<ExitStatus> {
+        self.signal(signal);
+
+        let deadline = Instant::now() + grace_period;
+        let status = loop {
+            if let Some(status) = self.child.try_wait()? {
+                break status;
+            }
+
+            if Instant::now() >= deadline {
+                self.signal(libc::SIGKILL);
+                break self.child.wait()?;
+            }
+
+            thread::sleep(POLL_INTERVAL);
+        };
+
+        // give the terminal back before the next command is started
+        self.foreground = None;
+
+        Ok(status)
+    }
+}
+
+/// Reports changes to any of the files through `tx`. The directories containing the files are
+/// watched instead of the files themselves, so that files replaced by editors are noticed too.
+fn watch(paths: &[PathBuf], tx: Sender<Event>) -> Result<()> {
+    let mut inotify = Inotify::init().context("Could not initialize inotify")?;
+    let mut names: HashMap<inotify::WatchDescriptor, Vec<OsString>> = HashMap::new();
+
+    for path in paths {
+        let dir = match path.parent() {
+            Some(v) if v != Path::new("") => v,
+            _ => Path::new(".")
+        };
+        let name = match path.file_name() {
+            Some(v) => v.to_os_string(),
+            None => continue
+        };
+
+        let wd = inotify
+            .add_watch(dir, WatchMask::CLOSE_WRITE | WatchMask::MOVED_TO | WatchMask::CREATE | WatchMask::DELETE)
+            .context(format!("Could not watch {}", dir.to_str().unwrap_or("<unknown>")))?;
+
+        names.entry(wd).or_default().push(name);
+    }
+
+    thread::spawn(move || {
+        let mut buffer = [0u8; 4096];
+
+        loop {
+            let events = match inotify.read_events_blocking(&mut buffer) {
+                Ok(v) => v,
+                Err(_) => return
+            };
+
+            let changed = events.into_iter().any(|e| match (names.get(&e.wd), e.name) {
+                (Some(names), Some(name)) => names.iter().any(|n| n == name),
+                _ => false
+            });
+
+            if changed && tx.send(Event::Changed).is_err() {
+                return;
+            }
+        }
+    });
+
+    Ok(())
+}
+
+/// Calls `vault_changed` every `interval` and reports changes through `tx`. It runs on its own
+/// thread, so the slow network calls don't hold up relaying signals or noticing the command exit.
+fn poll(interval: Duration, mut vault_changed: Box<dyn FnMut() -> Result<bool> + Send>, tx: Sender<Event>) {
+    thread::spawn(move || {
+        loop {
+            thread::sleep(interval);
+
+            match vault_changed() {
+                Ok(true) => if tx.send(Event::VaultChanged).is_err() {
+                    return;
+                },
+                Ok(false) => (),
+                Err(e) => eprintln!("Could not check the vault for changes: {:#}", e)
+            }
+        }
+    });
+}
+
+/// Waits for `delay` to pass. Returns false if nxc was asked to stop in the meantime.
+fn wait_restart(rx: &Receiver<Event>, delay: Duration) -> bool {
+    let deadline = Instant::now() + delay;
+
+    while let Some(timeout) = deadline.checked_duration_since(Instant::now()) {
+        match rx.recv_timeout(timeout) {
+            Ok(Event::Signal(received)) if is_terminating(received.signal) => return false,
+            Ok(_) | Err(RecvTimeoutError::Timeout) => (),
+            Err(RecvTimeoutError::Disconnected) => thread::sleep(timeout)
+        }
+    }
+
+    true
+}
+
+/// Runs the command and restarts it whenever one of the `watched` files changes, or `vault_changed`
+/// reports a change when polled, and `resolve` produces a different environment. Forwarded signals
+/// are relayed to the command. When the command exits on its own, it is started again with a
+/// freshly resolved environment if the restart policy allows it, otherwise nxc exits as well.
+/// `write_files` writes the files the command reads, as loaded by the last `resolve`, and reports
+/// whether any of them changed. Returns the exit code nxc should exit with.
+pub fn supervise(
+    envs: Envs,
+    resolve: &mut dyn FnMut() -> Result<Envs>,
+    build: &dyn Fn(&Envs) -> Result<Command>,
+    write_files: &dyn Fn() -> Result<bool>,
+    watched: &[PathBuf],
+    vault_changed: Box<dyn FnMut() -> Result<bool> + Send>,
+    options: &Options,
+) -> Result<i32> {
+
+    let (tx, rx) = mpsc::channel::<Event>();
+
+    // register the handlers before spawning so no signal is lost in between
+    let mut signals = process::signals()?;
+    let handle = signals.handle();
+    let signal_tx = tx.clone();
+    thread::spawn(move || {
+        for origin in signals.forever() {
+            if signal_tx.send(Event::Signal(origin.into())).is_err() {
+                return;
+            }
+        }
+    });
+
+    if let Some(interval) = options.poll_interval {
+        poll(interval, vault_changed, tx.clone());
+    }
+    watch(watched, tx)?;
+
+    let mut current = envs;
+    let mut running = Running::spawn(build(&current)?)?;
+    let mut started = Instant::now();
+    // consecutive restarts after the command exited on its own
+    let mut attempts: u32 = 0;
+    // set once nxc was asked to stop
+    let mut stopping = false;
+    // a reload is due once this passes without further changes
+    let mut pending: Option<Instant> = None;
+
+    let status = loop {
+        let timeout = match pending {
+            Some(deadline) => deadline
+                .checked_duration_since(Instant::now())
+                .unwrap_or_default()
+                .min(POLL_INTERVAL),
+            None => POLL_INTERVAL
+        };
+
+        match rx.recv_timeout(timeout) {
+            Ok(Event::Signal(received)) => {
+                stopping = stopping || is_terminating(received.signal);
+                running.target.forward(received);
+            },
+            Ok(Event::Changed) => pending = Some(Instant::now() + options.debounce),
+            Ok(Event::VaultChanged) => pending = Some(Instant::now()),
+            Err(RecvTimeoutError::Timeout) => (),
+            Err(RecvTimeoutError::Disconnected) => thread::sleep(timeout)
+        }
+
+        if let Some(status) = running.child.try_wait()? {
+            let policy = &options.restart;
+            if stopping || !policy.applies_to(&status) {
+                break status;
+            }
+
+            if started.elapsed() >= policy.success_threshold {
+                attempts = 0;
+            }
+            attempts += 1;
+
+            if let Some(max) = policy.max_retries {
+                if attempts > max {
+                    if !options.quiet {
+                        println!("Command exited with code {}. Giving up after {} restarts.",
+                            process::exit_code(status), max);
+                    }
+                    break status;
+                }
+            }
+
+            let delay = policy.delay(attempts);
+            if !options.quiet {
+                println!("Command exited with code {}. Restarting in {:.1}s ..",
+                    process::exit_code(status), delay.as_secs_f64());
+            }
+
+            // give the terminal back while waiting
+            running.foreground = None;
+
+            if !wait_restart(&rx, delay) {
+                break status;
+            }
+
+            // pick up any secrets that changed in the meantime
+            match resolve() {
+                Ok(envs) => current = envs,
+                Err(e) => eprintln!("Could not reload environment, using the previous one: {:#}", e)
+            }
+
+            running = Running::spawn(build(&current)?)?;
+            started = Instant::now();
+            pending = None;
+            continue;
+        }
+
+        match pending {
+            Some(deadline) if Instant::now() >= deadline => pending = None,
+            _ => continue
+        }
+
+        // the files may change while the variables pointing to them don't
+        let resolved = resolve().and_then(|envs| Ok((envs, write_files()?)));
+
+        match resolved {
+            Ok((envs, false)) if !differs(&envs, &current) => {
+                if !options.quiet {
+                    println!("Environment did not change.");
+                }
+            },
+            Ok((envs, _)) => match options.reload_signal {
+                Some(signal) => {
+                    if !options.quiet {
+                        println!("Environment changed. Signaling command ..");
+                    }
+
+                    current = envs;
+                    running.signal(signal);
+                },
+                None => {
+                    if !options.quiet {
+                        println!("Environment changed. Restarting command ..");
+                    }
+
+                    running.stop(options.stop_signal, options.grace_period)?;
+                    current = envs;
+                    running = Running::spawn(build(&current)?)?;
+                    started = Instant::now();
+                }
+            },
+            Err(e) => {
+                eprintln!("Could not reload environment, the command was not restarted: {:#}", e);
+            }
+        }
+    };
+
+    handle.close();
+    drop(running);
+
+    Ok(process::exit_code(status))
+}